@@ -0,0 +1,37 @@
+use esp_hal::{
+    Blocking,
+    analog::adc::{Adc, AdcCalCurve, AdcConfig, AdcPin, Attenuation},
+    peripherals::{ADC1, GPIO5},
+};
+
+use crate::battery::VoltageSensor;
+
+// The battery is connected through a divider that halves its voltage.
+const DIVIDER: u16 = 2;
+
+type CalibratedPin<'d> = AdcPin<GPIO5<'d>, ADC1<'d>, AdcCalCurve<ADC1<'d>>>;
+
+/// The battery voltage on GPIO5.
+pub struct BatteryAdc<'d> {
+    adc: Adc<'d, ADC1<'d>, Blocking>,
+    pin: CalibratedPin<'d>,
+}
+
+impl<'d> BatteryAdc<'d> {
+    pub fn new(adc: ADC1<'d>, pin: GPIO5<'d>) -> Self {
+        let mut config = AdcConfig::new();
+        // Measures up to about 3 V, enough for half of a full cell.
+        let pin = config.enable_pin_with_cal(pin, Attenuation::_11dB);
+        Self {
+            adc: Adc::new(adc, config),
+            pin,
+        }
+    }
+}
+
+impl VoltageSensor for BatteryAdc<'_> {
+    fn read_mv(&mut self) -> u16 {
+        // The curve calibration returns millivolts.
+        self.adc.read_blocking(&mut self.pin) * DIVIDER
+    }
+}
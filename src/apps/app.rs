@@ -1,9 +1,14 @@
-use mem_fs::MemFs;
+use embedded_graphics::pixelcolor::Rgb565;
+use esp_hal::time::Instant;
 
 use crate::{
     graphics::ScreenGrid,
     input::{ButtonEvent, ButtonManager},
-    system::{SettingsView, SystemCmd},
+    keyboard::{KeyEvent, TextBuffer},
+    keys::HwKeyEvent,
+    storage::Storage,
+    system::{SettingsView, SystemCmd, SystemResult},
+    timers::{TimerId, TimerService},
     touch::TouchEvent,
 };
 
@@ -11,7 +16,8 @@ pub struct Context<'a> {
     pub grid: &'a mut ScreenGrid<'a>,
     pub buttons: &'a mut ButtonManager,
     pub settings: SettingsView<'a>,
-    pub fs: &'a mut MemFs,
+    pub storage: Storage<'a>,
+    pub timers: &'a mut TimerService,
 }
 
 pub trait App {
@@ -19,29 +25,111 @@ pub trait App {
     fn update(&mut self, input: InputEvents, ctx: &mut Context) -> AppResponse;
     fn render(&mut self, ctx: &mut Context);
     fn get_name(&self) -> &'static str;
+
+    /// Keep the app alive while another app is active, instead of dropping it.
+    fn keep_alive(&self) -> bool {
+        false
+    }
+    /// Called when switching away from an app that is kept alive.
+    fn on_suspend(&mut self, _ctx: &mut Context) {}
+    /// Called instead of `init` when switching back to a suspended app.
+    /// The grid and buttons belong to the previous app, so they have to be set up again.
+    fn on_resume(&mut self, ctx: &mut Context) -> AppResponse {
+        self.init(ctx)
+    }
+    /// Called before the app is dropped.
+    fn on_exit(&mut self, _ctx: &mut Context) {}
+    /// Called when BACK is pressed, return a response to handle it in the app
+    /// instead of going back to the previous screen.
+    fn on_back(&mut self, _ctx: &mut Context) -> Option<AppResponse> {
+        None
+    }
+    /// Called when BACK closes a sub-screen opened with `AppCmd::PushScreen`.
+    fn on_close_screen(&mut self, _screen: &'static str, _ctx: &mut Context) -> AppResponse {
+        AppResponse::none()
+    }
 }
 
-// TODO: Generate this enum using a macro.
-#[derive(PartialEq)]
-pub enum AppID {
-    HomeApp,
-    ColorPicker,
-    SnakeApp,
-    TestApp,
-    SettingsApp,
+// Generated by the app registry in `apps/mod.rs`.
+pub use crate::apps::AppID;
+
+// Chosen by the calling app to tell its results apart.
+pub type RequestId = u8;
+
+/// Arguments of an intent, and the result returned by the opened app.
+#[derive(PartialEq, Clone, Debug)]
+pub enum IntentData {
+    None,
+    Text(TextBuffer),
+    Color(Rgb565),
+    Number(i32),
+}
+
+/// Open `app` with `data`, the calling app receives the result as `IntentResult` with the same `request`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Intent {
+    pub app: AppID,
+    pub request: RequestId,
+    pub data: IntentData,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct IntentResult {
+    pub request: RequestId,
+    // `IntentData::None` when the app was closed without returning a result.
+    pub data: IntentData,
 }
 
 #[derive(PartialEq)]
 pub enum AppCmd {
     None,
     Dirty,
+    // Replace the current app.
     SwitchApp(AppID),
+    // Open an app on top of the current one, BACK returns to the current app.
+    PushApp(AppID),
+    // Open a sub-screen of the current app, only used for BACK and the breadcrumb.
+    PushScreen(&'static str),
+    // Close the current screen, like BACK but without calling `App::on_back`.
+    Back,
+    // Open an app with arguments, its result is passed back to the current app.
+    Start(Intent),
+    // Close the current app and return the result to the app that started it.
+    Finish(IntentData),
 }
 
 #[derive(PartialEq)]
 pub struct InputEvents {
+    // When the input happened, or the start of the frame when there is no input.
+    pub time: Instant,
     pub touch: Option<TouchEvent>,
     pub button: Option<ButtonEvent>,
+    pub key: Option<KeyEvent>,
+    pub hw_key: Option<HwKeyEvent>,
+    // Set when a timer of the app fired.
+    pub timer: Option<TimerId>,
+    // Arguments of the intent that opened the app, set once after the app was opened.
+    pub intent: Option<IntentData>,
+    // Set once when an app started by this app finished.
+    pub result: Option<IntentResult>,
+    // Outcome of the `SystemCmd` in the previous response of the app.
+    pub system: Option<SystemResult>,
+}
+
+impl InputEvents {
+    pub const fn new(time: Instant) -> Self {
+        Self {
+            time,
+            touch: None,
+            button: None,
+            key: None,
+            hw_key: None,
+            timer: None,
+            intent: None,
+            result: None,
+            system: None,
+        }
+    }
 }
 
 pub struct AppResponse {
@@ -68,13 +156,43 @@ impl AppResponse {
             system: None,
         }
     }
+    pub const fn push(app: AppID) -> Self {
+        Self {
+            app: AppCmd::PushApp(app),
+            system: None,
+        }
+    }
+    pub const fn push_screen(screen: &'static str) -> Self {
+        Self {
+            app: AppCmd::PushScreen(screen),
+            system: None,
+        }
+    }
+    pub const fn back() -> Self {
+        Self {
+            app: AppCmd::Back,
+            system: None,
+        }
+    }
+    pub const fn start(intent: Intent) -> Self {
+        Self {
+            app: AppCmd::Start(intent),
+            system: None,
+        }
+    }
+    pub const fn finish(data: IntentData) -> Self {
+        Self {
+            app: AppCmd::Finish(data),
+            system: None,
+        }
+    }
     pub const fn system(cmd: SystemCmd) -> Self {
         Self {
             app: AppCmd::None,
             system: Some(cmd),
         }
     }
-    pub const fn with_system(mut self, cmd: SystemCmd) -> Self {
+    pub fn with_system(mut self, cmd: SystemCmd) -> Self {
         self.system = Some(cmd);
         self
     }
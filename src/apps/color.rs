@@ -2,7 +2,7 @@ use embedded_graphics::pixelcolor::Rgb565;
 use log::info;
 
 use crate::{
-    apps::app::{App, AppResponse, Context, InputEvents},
+    apps::app::{App, AppResponse, Context, InputEvents, IntentData},
     graphics::*,
     input::ButtonEvent,
 };
@@ -10,6 +10,8 @@ use crate::{
 pub struct ColorApp {
     colors: [(&'static str, Rgb565); 8],
     selected: u16,
+    // Opened with an intent, PICK returns the selected color.
+    picking: bool,
 }
 
 impl Default for ColorApp {
@@ -26,6 +28,7 @@ impl Default for ColorApp {
                 ("Green", GREEN),
             ],
             selected: 0,
+            picking: false,
         }
     }
 }
@@ -34,7 +37,7 @@ impl App for ColorApp {
     fn init(&mut self, ctx: &mut Context) -> AppResponse {
         ctx.buttons.clear();
         ctx.buttons.register_default_buttons();
-        ctx.buttons.register_button(
+        let _ = ctx.buttons.register_button(
             "NEXT",
             crate::input::Rect {
                 x_min: 120,
@@ -47,7 +50,32 @@ impl App for ColorApp {
         AppResponse::dirty()
     }
 
-    fn update(&mut self, input: InputEvents, _ctx: &mut Context) -> AppResponse {
+    fn update(&mut self, input: InputEvents, ctx: &mut Context) -> AppResponse {
+        // The intent can come with other input, which is handled as well.
+        let mut dirty = false;
+        if let Some(data) = input.intent {
+            // Start with the color passed by the caller.
+            if let IntentData::Color(color) = data
+                && let Some(idx) = self.colors.iter().position(|c| c.1 == color)
+            {
+                self.selected = idx as u16;
+            }
+            self.picking = true;
+            let _ = ctx.buttons.register_button(
+                "PICK",
+                crate::input::Rect {
+                    x_min: 120,
+                    y_min: 150,
+                    x_max: 172,
+                    y_max: 170,
+                },
+            );
+            dirty = true;
+        }
+        if self.picking && input.button == Some(ButtonEvent::Up("PICK")) {
+            let color = self.colors[self.selected as usize].1;
+            return AppResponse::finish(IntentData::Color(color));
+        }
         if let Some(button_event) = input.button {
             match button_event {
                 ButtonEvent::Up(id) => {
@@ -65,7 +93,11 @@ impl App for ColorApp {
                 _ => {}
             }
         }
-        AppResponse::none()
+        if dirty {
+            AppResponse::dirty()
+        } else {
+            AppResponse::none()
+        }
     }
     fn render(&mut self, ctx: &mut Context) {
         ctx.grid.clear(' ', BASE03, BASE03);
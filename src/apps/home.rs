@@ -1,87 +1,294 @@
+use esp_hal::time::Duration;
+use heapless::{String, Vec};
+use log::warn;
+
 use crate::{
-    apps::app::{App, AppID, AppResponse, Context, InputEvents},
+    apps::{
+        APPS, AppCategory, AppInfo,
+        app::{App, AppID, AppResponse, Context, InputEvents},
+    },
     graphics::*,
+    input::{ButtonEvent, Rect},
+    timers::TimerId,
 };
 
-pub struct HomeApp {}
+// Tiles per page, the page buttons are only shown when the apps do not fit on one page.
+const COLUMNS: u16 = 3;
+const ROWS: u16 = 3;
+const PAGE_SIZE: usize = (COLUMNS * ROWS) as usize;
+
+// Tile size and position, in pixels.
+const TILE_W: u16 = 72;
+const TILE_H: u16 = 60;
+const TILE_GAP_X: u16 = 6;
+const TILE_GAP_Y: u16 = 10;
+const FIRST_TILE_Y: u16 = 40;
+
+// Holding a tile this long shows the app info instead of opening the app.
+const LONG_PRESS_TIMER: TimerId = 0;
+const LONG_PRESS_TIME: Duration = Duration::from_millis(600);
+
+// Recently used apps by name, most recent first, one per line.
+const RECENT_KEY: &str = "recent";
+// 1 when the recently used apps are shown first.
+const ORDER_KEY: &str = "order";
+const MAX_RECENT: usize = 8;
+const MAX_RECENT_LEN: usize = 128;
+
+const INFO_ROW: u16 = 26;
+const PAGE_ROW: u16 = 29;
+
+pub struct HomeApp {
+    page: usize,
+    recent_first: bool,
+    recent: Vec<AppID, MAX_RECENT>,
+    // Tile that is held down, and whether it was held long enough to show the info.
+    pressed: Option<AppID>,
+    long_press: bool,
+    info: Option<AppID>,
+}
 
 impl Default for HomeApp {
     fn default() -> Self {
-        Self {}
+        Self {
+            page: 0,
+            recent_first: false,
+            recent: Vec::new(),
+            pressed: None,
+            long_press: false,
+            info: None,
+        }
     }
 }
 
-impl App for HomeApp {
-    fn init(&mut self, ctx: &mut Context) -> AppResponse {
+impl HomeApp {
+    // Every registered app except the launcher itself, in launcher order.
+    fn entries(&self) -> Vec<&'static AppInfo, { APPS.len() }> {
+        let mut entries: Vec<&'static AppInfo, { APPS.len() }> = APPS
+            .iter()
+            .filter(|info| info.id != AppID::HomeApp)
+            .collect();
+        if self.recent_first {
+            // Apps that were never used stay in registry order.
+            entries.sort_unstable_by_key(|info| {
+                let recent = self.recent.iter().position(|id| *id == info.id);
+                (recent.unwrap_or(MAX_RECENT), info.id as u8)
+            });
+        }
+        entries
+    }
+    fn page_count(&self) -> usize {
+        self.entries().len().div_ceil(PAGE_SIZE).max(1)
+    }
+    // Register the tiles of the current page.
+    fn layout(&mut self, ctx: &mut Context) {
         ctx.grid.clear(' ', BASE03, BASE03);
         ctx.buttons.clear();
+        self.page = self.page.min(self.page_count() - 1);
 
-        ctx.buttons.register_button(
-            "TEST",
-            crate::input::Rect {
-                x_min: 0,
-                y_min: 60,
-                x_max: 80,
-                y_max: 80,
-            },
-        );
-        ctx.buttons.register_button(
-            "COLOR",
-            crate::input::Rect {
-                x_min: 0,
-                y_min: 90,
-                x_max: 80,
-                y_max: 110,
-            },
-        );
-        ctx.buttons.register_button(
-            "SNAKE",
-            crate::input::Rect {
-                x_min: 0,
-                y_min: 120,
-                x_max: 80,
-                y_max: 140,
-            },
-        );
-        ctx.buttons.register_button(
-            "SETTINGS",
-            crate::input::Rect {
-                x_min: 0,
-                y_min: 150,
-                x_max: 80,
-                y_max: 170,
+        let _ = ctx.buttons.register_button(
+            "SORT",
+            Rect {
+                x_min: 198,
+                y_min: 20,
+                x_max: 234,
+                y_max: 30,
             },
         );
 
+        let entries = self.entries();
+        for (idx, info) in entries
+            .iter()
+            .skip(self.page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .enumerate()
+        {
+            let col = idx as u16 % COLUMNS;
+            let row = idx as u16 / COLUMNS;
+            let x = TILE_GAP_X + col * (TILE_W + TILE_GAP_X);
+            let y = FIRST_TILE_Y + row * (TILE_H + TILE_GAP_Y);
+            let _ = ctx.buttons.register_icon_button(
+                info.name,
+                info.icon,
+                Rect {
+                    x_min: x,
+                    y_min: y,
+                    x_max: x + TILE_W,
+                    y_max: y + TILE_H,
+                },
+            );
+        }
+
+        if self.page_count() > 1 {
+            let y = PAGE_ROW * CELL_H;
+            let _ = ctx.buttons.register_button(
+                "<",
+                Rect {
+                    x_min: TILE_GAP_X,
+                    y_min: y,
+                    x_max: TILE_GAP_X + 24,
+                    y_max: y + 20,
+                },
+            );
+            let _ = ctx.buttons.register_button(
+                ">",
+                Rect {
+                    x_min: 240 - TILE_GAP_X - 24,
+                    y_min: y,
+                    x_max: 240 - TILE_GAP_X,
+                    y_max: y + 20,
+                },
+            );
+        }
+    }
+    fn load_recent(&mut self, ctx: &Context) {
+        self.recent.clear();
+        self.recent_first = ctx.storage.get_u8(ORDER_KEY) == Some(1);
+        let Some(data) = ctx.storage.read(RECENT_KEY) else {
+            return;
+        };
+        for name in core::str::from_utf8(data).unwrap_or_default().lines() {
+            if let Some(id) = AppID::from_name(name) {
+                let _ = self.recent.push(id);
+            }
+        }
+    }
+    // Move `app` to the front of the recently used apps and save them.
+    fn add_recent(&mut self, app: AppID, ctx: &mut Context) {
+        self.recent.retain(|id| *id != app);
+        if self.recent.is_full() {
+            self.recent.pop();
+        }
+        let _ = self.recent.insert(0, app);
+
+        let mut data: String<MAX_RECENT_LEN> = String::new();
+        for id in &self.recent {
+            let _ = data.push_str(id.info().name);
+            let _ = data.push('\n');
+        }
+        if let Err(err) = ctx.storage.write(RECENT_KEY, data.as_bytes()) {
+            warn!("Failed to save recent apps: {:?}", err);
+        }
+    }
+    fn open(&mut self, app: AppID, ctx: &mut Context) -> AppResponse {
+        self.add_recent(app, ctx);
+        AppResponse::push(app)
+    }
+}
+
+fn category_name(category: AppCategory) -> &'static str {
+    match category {
+        AppCategory::System => "System",
+        AppCategory::Games => "Games",
+        AppCategory::Tools => "Tools",
+    }
+}
+
+impl App for HomeApp {
+    fn init(&mut self, ctx: &mut Context) -> AppResponse {
+        self.load_recent(ctx);
+        self.pressed = None;
+        self.long_press = false;
+        self.info = None;
+        self.layout(ctx);
+
         AppResponse::dirty()
     }
-    fn update(&mut self, input: InputEvents, _ctx: &mut Context) -> AppResponse {
-        if let Some(button_event) = input.button {
-            match button_event {
-                crate::input::ButtonEvent::Up(id) => {
-                    if id == "TEST" {
-                        return AppResponse::switch(AppID::TestApp);
-                    }
-                    if id == "COLOR" {
-                        return AppResponse::switch(AppID::ColorPicker);
+    fn update(&mut self, input: InputEvents, ctx: &mut Context) -> AppResponse {
+        if input.timer == Some(LONG_PRESS_TIMER) && self.pressed.is_some() {
+            self.info = self.pressed;
+            self.long_press = true;
+            return AppResponse::dirty();
+        }
+
+        match input.button {
+            Some(ButtonEvent::Down(id)) => {
+                let app = AppID::from_name(id).filter(|app| *app != AppID::HomeApp);
+                if app.is_some() && self.pressed != app {
+                    self.pressed = app;
+                    ctx.timers.start_oneshot(LONG_PRESS_TIMER, LONG_PRESS_TIME);
+                }
+            }
+            Some(ButtonEvent::Up(id)) => {
+                ctx.timers.cancel(LONG_PRESS_TIMER);
+                self.pressed = None;
+                // The tile was held to show the info, so do not open it.
+                if core::mem::take(&mut self.long_press) {
+                    return AppResponse::none();
+                }
+                self.info = None;
+
+                match id {
+                    "<" | ">" => {
+                        let pages = self.page_count();
+                        self.page = if id == ">" {
+                            (self.page + 1) % pages
+                        } else {
+                            (self.page + pages - 1) % pages
+                        };
+                        self.layout(ctx);
+                        return AppResponse::dirty();
                     }
-                    if id == "SNAKE" {
-                        return AppResponse::switch(AppID::SnakeApp);
+                    "SORT" => {
+                        self.recent_first = !self.recent_first;
+                        let _ = ctx.storage.put_u8(ORDER_KEY, self.recent_first as u8);
+                        self.page = 0;
+                        self.layout(ctx);
+                        return AppResponse::dirty();
                     }
-                    if id == "SETTINGS" {
-                        return AppResponse::switch(AppID::SettingsApp);
+                    _ => {
+                        if let Some(app) = AppID::from_name(id)
+                            && app != AppID::HomeApp
+                        {
+                            return self.open(app, ctx);
+                        }
                     }
                 }
-                _ => {}
             }
+            None => {}
         }
 
         AppResponse::none()
     }
     fn render(&mut self, ctx: &mut Context) {
-        ctx.grid.write_str(0, 3, "Welcome!", BASE3, BASE03);
-        ctx.grid
-            .write_str(0, 4, "Select an app to get started.", BASE2, BASE03);
+        let order = if self.recent_first {
+            "Recently used first"
+        } else {
+            "All apps"
+        };
+        ctx.grid.write_str(1, 2, order, BASE2, BASE03);
+
+        // Clear the info and page rows, they change without a new layout.
+        ctx.grid.draw_box(0, INFO_ROW, 40, 2, BASE03);
+        ctx.grid.draw_box(5, PAGE_ROW, 30, 2, BASE03);
+
+        match self.info.map(AppID::info) {
+            Some(info) => {
+                let mut line: String<40> = String::new();
+                let _ = line.push(info.icon);
+                let _ = line.push(' ');
+                let _ = line.push_str(info.name);
+                let _ = line.push_str(" - ");
+                let _ = line.push_str(category_name(info.category));
+                ctx.grid.center_str(INFO_ROW, &line, BASE3, BASE03);
+
+                let used = match self.recent.iter().position(|id| *id == info.id) {
+                    Some(0) => "Used last",
+                    Some(_) => "Used recently",
+                    None => "Not used yet",
+                };
+                ctx.grid.center_str(INFO_ROW + 1, used, BASE1, BASE03);
+            }
+            None => ctx
+                .grid
+                .center_str(INFO_ROW, "Hold an app for info.", BASE01, BASE03),
+        }
+
+        let pages = self.page_count();
+        if pages > 1 {
+            let page = heapless::format!(8; "{}/{}", self.page + 1, pages).unwrap_or_default();
+            ctx.grid.center_str(PAGE_ROW + 1, &page, BASE2, BASE03);
+        }
     }
     fn get_name(&self) -> &'static str {
         "HOME"
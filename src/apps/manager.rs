@@ -0,0 +1,343 @@
+use heapless::{String, Vec};
+use log::warn;
+
+use crate::{
+    apps::{
+        AppID, AppState,
+        app::{
+            App, AppResponse, Context, InputEvents, Intent, IntentData, IntentResult, RequestId,
+        },
+    },
+    crash,
+    system::SystemCmd,
+};
+
+// Apps kept alive in the background. The least recently used one is closed first, unless it waits for a
+// result.
+pub const MAX_SUSPENDED: usize = 2;
+
+// Entries on the navigation stack, no more apps or screens are opened when it is full.
+pub const MAX_NAV_DEPTH: usize = 8;
+
+// Fits the title bar.
+pub const BREADCRUMB_LEN: usize = 40;
+
+const BREADCRUMB_SEPARATOR: &str = " > ";
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct NavEntry {
+    pub app: AppID,
+    // Sub-screen of the app, `None` for the app itself.
+    pub screen: Option<&'static str>,
+    // Set when the app was opened with an intent, the previous entry waits for its result.
+    pub request: Option<RequestId>,
+}
+
+impl NavEntry {
+    fn app(app: AppID) -> Self {
+        Self {
+            app,
+            screen: None,
+            request: None,
+        }
+    }
+    fn name(&self) -> &'static str {
+        self.screen.unwrap_or(self.app.info().name)
+    }
+}
+
+/// Owns the active app, the suspended apps that asked to be kept alive and the navigation stack.
+pub struct AppManager {
+    active: AppState,
+    // Ordered from least to most recently used.
+    suspended: Vec<AppState, MAX_SUSPENDED>,
+    // The last entry is the current screen.
+    stack: Vec<NavEntry, MAX_NAV_DEPTH>,
+    // Delivered with the next update of the active app.
+    intent: Option<IntentData>,
+    result: Option<IntentResult>,
+}
+
+impl AppManager {
+    /// Create the manager with `app` as the active app, `init` still has to be called.
+    pub fn new(app: AppID) -> Self {
+        let mut stack = Vec::new();
+        let _ = stack.push(NavEntry::app(app));
+        Self {
+            active: AppState::new(app),
+            suspended: Vec::new(),
+            stack,
+            intent: None,
+            result: None,
+        }
+    }
+    pub fn active_id(&self) -> AppID {
+        self.active.id()
+    }
+    /// The active app has intent arguments or a result that was not delivered yet.
+    pub fn has_pending(&self) -> bool {
+        self.intent.is_some() || self.result.is_some()
+    }
+    /// Open `app` on top of the current screen.
+    pub fn push(&mut self, app: AppID, ctx: &mut Context) -> AppResponse {
+        if app == self.active.id() {
+            return AppResponse::none();
+        }
+        if self.stack.is_full() {
+            return refused(AppResponse::none(), "Too many screens open");
+        }
+        let _ = self.stack.push(NavEntry::app(app));
+        self.activate(app, false, ctx)
+    }
+    /// Open the app of `intent` on top of the current screen.
+    ///
+    /// The current app is kept alive until the opened app finishes, then it receives the result.
+    /// When it cannot be kept alive, the app is not opened and the result is `IntentData::None`.
+    pub fn start(&mut self, intent: Intent, ctx: &mut Context) -> AppResponse {
+        if intent.app == self.active.id() {
+            warn!("{:?} cannot start itself", intent.app);
+            return AppResponse::none();
+        }
+        let reason = if self.stack.is_full() {
+            Some("Too many screens open")
+        } else if !self.can_suspend(intent.app) {
+            Some("Too many apps waiting")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            self.result = Some(IntentResult {
+                request: intent.request,
+                data: IntentData::None,
+            });
+            return refused(AppResponse::none(), reason);
+        }
+        let _ = self.stack.push(NavEntry {
+            request: Some(intent.request),
+            ..NavEntry::app(intent.app)
+        });
+        let response = self.activate(intent.app, true, ctx);
+        self.intent = Some(intent.data);
+        response
+    }
+    /// Close the active app and its sub-screens, `data` is returned to the app that started it.
+    pub fn finish(&mut self, data: IntentData, ctx: &mut Context) -> AppResponse {
+        let active = self.active.id();
+        while self.stack.len() > 1
+            && self
+                .stack
+                .last()
+                .is_some_and(|entry| entry.app == active && entry.screen.is_some())
+        {
+            self.stack.pop();
+        }
+        self.close(data, ctx)
+    }
+    /// Open a sub-screen of the active app on top of the current screen.
+    ///
+    /// When the stack is full the app is told to close the screen again, as if BACK was pressed.
+    pub fn push_screen(&mut self, screen: &'static str, ctx: &mut Context) -> AppResponse {
+        if self.stack.is_full() {
+            let response = self.active.on_close_screen(screen, ctx);
+            return refused(response, "Too many screens open");
+        }
+        let _ = self.stack.push(NavEntry {
+            screen: Some(screen),
+            ..NavEntry::app(self.active.id())
+        });
+        AppResponse::none()
+    }
+    /// Replace the active app and its sub-screens with `app`.
+    pub fn switch(&mut self, app: AppID, ctx: &mut Context) -> AppResponse {
+        let active = self.active.id();
+        while self.stack.last().is_some_and(|entry| entry.app == active) {
+            self.stack.pop();
+        }
+        // Fits, the active app had at least one entry.
+        let _ = self.stack.push(NavEntry::app(app));
+        self.activate(app, false, ctx)
+    }
+    /// Go back to the previous screen. Without one, go to the home screen.
+    ///
+    /// The active app can intercept this with `App::on_back`.
+    pub fn back(&mut self, ctx: &mut Context) -> AppResponse {
+        if let Some(response) = self.active.on_back(ctx) {
+            return response;
+        }
+        self.pop(ctx)
+    }
+    /// Close the current screen without asking the app, see `back`.
+    ///
+    /// An app opened with an intent returns `IntentData::None`.
+    pub fn pop(&mut self, ctx: &mut Context) -> AppResponse {
+        self.close(IntentData::None, ctx)
+    }
+    /// Clear the navigation stack and go to the home screen.
+    pub fn home(&mut self, ctx: &mut Context) -> AppResponse {
+        self.stack.clear();
+        let _ = self.stack.push(NavEntry::app(AppID::HomeApp));
+        self.activate(AppID::HomeApp, false, ctx)
+    }
+    /// Drop the active app without calling its hooks and go to the home screen.
+    ///
+    /// Used for apps that misbehave, running their code again could hang the device.
+    pub fn kill(&mut self, ctx: &mut Context) -> AppResponse {
+        ctx.timers.clear();
+        self.intent = None;
+        self.result = None;
+        // The home screen is created again, a suspended one is dropped.
+        self.suspended.retain(|state| state.id() != AppID::HomeApp);
+        self.active = AppState::new(AppID::HomeApp);
+        self.stack.clear();
+        let _ = self.stack.push(NavEntry::app(AppID::HomeApp));
+        self.init(ctx)
+    }
+    /// Names of the screens on the navigation stack, for the title bar.
+    ///
+    /// The oldest entries are left out when it does not fit.
+    pub fn breadcrumb(&self) -> String<BREADCRUMB_LEN> {
+        let sep = BREADCRUMB_SEPARATOR.len();
+        let mut first = self.stack.len() - 1;
+        let mut len = self.stack[first].name().len();
+        while first > 0 && len + sep + self.stack[first - 1].name().len() <= BREADCRUMB_LEN {
+            first -= 1;
+            len += sep + self.stack[first].name().len();
+        }
+
+        let mut breadcrumb = String::new();
+        for (idx, entry) in self.stack[first..].iter().enumerate() {
+            if idx > 0 {
+                let _ = breadcrumb.push_str(BREADCRUMB_SEPARATOR);
+            }
+            let _ = breadcrumb.push_str(entry.name());
+        }
+        breadcrumb
+    }
+
+    // Close the current screen, `data` is the result if it was opened with an intent.
+    fn close(&mut self, data: IntentData, ctx: &mut Context) -> AppResponse {
+        if self.stack.len() <= 1 {
+            return self.home(ctx);
+        }
+        let closed = self.stack.pop().expect("stack has more than one entry");
+        let current = *self.stack.last().expect("stack has more than one entry");
+        if let Some(screen) = closed.screen
+            && current.app == closed.app
+        {
+            return self.active.on_close_screen(screen, ctx);
+        }
+        let response = self.activate(current.app, false, ctx);
+        if let Some(request) = closed.request {
+            self.result = Some(IntentResult { request, data });
+        }
+        response
+    }
+    // Whether an app on the stack started the app above it and waits for its result.
+    fn waits_for_result(&self, app: AppID) -> bool {
+        self.stack
+            .windows(2)
+            .any(|pair| pair[0].app == app && pair[1].request.is_some())
+    }
+    // Whether the active app can be suspended to open `app`.
+    fn can_suspend(&self, app: AppID) -> bool {
+        !self.suspended.is_full()
+            || self
+                .suspended
+                .iter()
+                .any(|state| state.id() == app || !self.waits_for_result(state.id()))
+    }
+    // Close the least recently used suspended app if there is no room for another one.
+    // Returns false when every suspended app waits for a result.
+    fn make_room(&mut self, ctx: &mut Context) -> bool {
+        if !self.suspended.is_full() {
+            return true;
+        }
+        let Some(idx) = self
+            .suspended
+            .iter()
+            .position(|state| !self.waits_for_result(state.id()))
+        else {
+            return false;
+        };
+        let mut closed = self.suspended.remove(idx);
+        let namespace = ctx.storage.namespace();
+        ctx.storage.set_namespace(closed.id().info().name);
+        closed.on_exit(ctx);
+        ctx.storage.set_namespace(namespace);
+        true
+    }
+    // Make `app` the active app, resuming it if it was suspended.
+    // The previous app is suspended if it wants to be kept alive or waits for a result, otherwise it is closed.
+    fn activate(&mut self, app: AppID, keep_previous: bool, ctx: &mut Context) -> AppResponse {
+        if app == self.active.id() {
+            return AppResponse::none();
+        }
+        // Arguments and results are only meant for the app they were sent to.
+        self.intent = None;
+        self.result = None;
+
+        let resumed = self
+            .suspended
+            .iter()
+            .position(|state| state.id() == app)
+            .map(|idx| self.suspended.remove(idx));
+        let is_resumed = resumed.is_some();
+
+        // Timers belong to the app that started them.
+        ctx.timers.clear();
+
+        let mut previous = core::mem::replace(
+            &mut self.active,
+            resumed.unwrap_or_else(|| AppState::new(app)),
+        );
+        // `start` checked that an app waiting for a result can be kept.
+        if (keep_previous || previous.keep_alive()) && self.make_room(ctx) {
+            previous.on_suspend(ctx);
+            let _ = self.suspended.push(previous);
+        } else {
+            previous.on_exit(ctx);
+        }
+        ctx.storage.set_namespace(app.info().name);
+        crash::set_active_app(app.info().name);
+
+        if is_resumed {
+            self.active.on_resume(ctx)
+        } else {
+            self.active.init(ctx)
+        }
+    }
+}
+
+// Tell the user why an app or screen was not opened.
+fn refused(response: AppResponse, reason: &str) -> AppResponse {
+    warn!("{}", reason);
+    match response.system {
+        Some(_) => response,
+        None => response.with_system(SystemCmd::ShowToast(
+            String::try_from(reason).unwrap_or_default(),
+        )),
+    }
+}
+
+impl App for AppManager {
+    fn init(&mut self, ctx: &mut Context) -> AppResponse {
+        ctx.storage.set_namespace(self.active.id().info().name);
+        crash::set_active_app(self.active.id().info().name);
+        self.active.init(ctx)
+    }
+    fn update(&mut self, mut input: InputEvents, ctx: &mut Context) -> AppResponse {
+        input.intent = self.intent.take();
+        input.result = self.result.take();
+        self.active.update(input, ctx)
+    }
+    fn render(&mut self, ctx: &mut Context) {
+        self.active.render(ctx);
+    }
+    fn get_name(&self) -> &'static str {
+        self.active.get_name()
+    }
+    /// Resume the active app in place, after a system screen was drawn over it.
+    fn on_resume(&mut self, ctx: &mut Context) -> AppResponse {
+        self.active.on_resume(ctx)
+    }
+}
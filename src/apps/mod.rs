@@ -1,5 +1,5 @@
 use crate::apps::{
-    app::{App, AppID, AppResponse, InputEvents},
+    app::{App, AppResponse, InputEvents},
     color::ColorApp,
     home::HomeApp,
     settings::SettingsApp,
@@ -10,45 +10,99 @@ use crate::apps::{
 pub mod app;
 pub mod color;
 pub mod home;
+pub mod manager;
 pub mod settings;
 pub mod snake;
 pub mod test;
 
-pub enum AppState {
-    Home(HomeApp),
-    Color(ColorApp),
-    Snake(SnakeApp),
-    Test(TestApp),
-    Settings(SettingsApp),
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AppCategory {
+    System,
+    Games,
+    Tools,
 }
 
-impl AppState {
-    fn app_mut(&mut self) -> &mut dyn App {
-        match self {
-            AppState::Home(app) => app,
-            AppState::Color(app) => app,
-            AppState::Snake(app) => app,
-            AppState::Test(app) => app,
-            AppState::Settings(app) => app,
+/// Launcher entry for an app.
+pub struct AppInfo {
+    pub id: AppID,
+    // Also used as the launcher button id.
+    pub name: &'static str,
+    pub icon: char,
+    pub category: AppCategory,
+}
+
+/// Generates `AppID`, `AppState` and the `APPS` table from one list of apps.
+///
+/// Adding an app only needs a new line in the `app_registry!` invocation below.
+macro_rules! app_registry {
+    ($($id:ident => $state:ident($app:ty) {
+        name: $name:literal,
+        icon: $icon:literal,
+        category: $category:ident $(,)?
+    }),* $(,)?) => {
+        #[derive(PartialEq, Clone, Copy, Debug)]
+        pub enum AppID {
+            $($id,)*
         }
-    }
-    fn app_ref(&self) -> &dyn App {
-        match self {
-            AppState::Home(app) => app,
-            AppState::Color(app) => app,
-            AppState::Snake(app) => app,
-            AppState::Test(app) => app,
-            AppState::Settings(app) => app,
+
+        pub enum AppState {
+            $($state($app),)*
         }
-    }
-    pub fn switch(&self, app: AppID) -> AppState {
-        match app {
-            AppID::HomeApp => AppState::Home(HomeApp::default()),
-            AppID::ColorPicker => AppState::Color(ColorApp::default()),
-            AppID::SnakeApp => AppState::Snake(SnakeApp::default()),
-            AppID::TestApp => AppState::Test(TestApp::default()),
-            AppID::SettingsApp => AppState::Settings(SettingsApp::default()),
+
+        /// Every registered app, in launcher order.
+        pub const APPS: &[AppInfo] = &[
+            $(AppInfo {
+                id: AppID::$id,
+                name: $name,
+                icon: $icon,
+                category: AppCategory::$category,
+            },)*
+        ];
+
+        impl AppState {
+            fn app_mut(&mut self) -> &mut dyn App {
+                match self {
+                    $(AppState::$state(app) => app,)*
+                }
+            }
+            fn app_ref(&self) -> &dyn App {
+                match self {
+                    $(AppState::$state(app) => app,)*
+                }
+            }
+            pub fn new(app: AppID) -> AppState {
+                match app {
+                    $(AppID::$id => AppState::$state(<$app>::default()),)*
+                }
+            }
+            pub fn id(&self) -> AppID {
+                match self {
+                    $(AppState::$state(_) => AppID::$id,)*
+                }
+            }
         }
+    };
+}
+
+app_registry! {
+    HomeApp => Home(HomeApp) { name: "HOME", icon: 'H', category: System },
+    TestApp => Test(TestApp) { name: "TEST", icon: 'T', category: Tools },
+    ColorPicker => Color(ColorApp) { name: "COLOR", icon: 'C', category: Tools },
+    SnakeApp => Snake(SnakeApp) { name: "SNAKE", icon: 'S', category: Games },
+    SettingsApp => Settings(SettingsApp) { name: "SETTINGS", icon: '*', category: System },
+}
+
+impl AppID {
+    pub fn info(self) -> &'static AppInfo {
+        APPS.iter()
+            .find(|info| info.id == self)
+            .expect("every AppID is registered")
+    }
+    /// Look up an app by name, ignoring case.
+    pub fn from_name(name: &str) -> Option<AppID> {
+        APPS.iter()
+            .find(|info| info.name.eq_ignore_ascii_case(name))
+            .map(|info| info.id)
     }
 }
 
@@ -65,4 +119,22 @@ impl App for AppState {
     fn get_name(&self) -> &'static str {
         self.app_ref().get_name()
     }
+    fn keep_alive(&self) -> bool {
+        self.app_ref().keep_alive()
+    }
+    fn on_suspend(&mut self, ctx: &mut app::Context) {
+        self.app_mut().on_suspend(ctx);
+    }
+    fn on_resume(&mut self, ctx: &mut app::Context) -> AppResponse {
+        self.app_mut().on_resume(ctx)
+    }
+    fn on_exit(&mut self, ctx: &mut app::Context) {
+        self.app_mut().on_exit(ctx);
+    }
+    fn on_back(&mut self, ctx: &mut app::Context) -> Option<AppResponse> {
+        self.app_mut().on_back(ctx)
+    }
+    fn on_close_screen(&mut self, screen: &'static str, ctx: &mut app::Context) -> AppResponse {
+        self.app_mut().on_close_screen(screen, ctx)
+    }
 }
@@ -1,10 +1,21 @@
 use esp_hal::time::{Duration, Instant};
 
 use crate::{
-    apps::app::{App, AppResponse, Context, InputEvents},
+    apps::{
+        APPS,
+        app::{App, AppResponse, Context, InputEvents},
+    },
+    battery::battery_state,
+    clock::{self, DateTime, TimeZone},
+    cpu,
+    crash::{self, CrashReport},
     graphics::*,
-    input::{ButtonEvent, Rect},
-    system::SystemCmd,
+    input::{ButtonEvent, ButtonId, Rect},
+    keys::HwKey,
+    power::{power_mode, wake_locks},
+    system::{SYSTEM_NAMESPACE, SystemCmd, SystemError},
+    timers::TimerId,
+    touch::TouchEvent,
 };
 
 pub const GIT_HASH: &str = match option_env!("GIT_HASH") {
@@ -12,146 +23,675 @@ pub const GIT_HASH: &str = match option_env!("GIT_HASH") {
     None => "unknown",
 };
 
-pub struct SettingsApp {
-    last_input_events: InputEvents,
-    last_update: Instant,
-    screen_brightness: u8,
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Page {
+    Display,
+    Power,
+    Clock,
+    Input,
+    Storage,
+    About,
+}
+
+// Menu button, page and description, the button id is also the name of the screen.
+const PAGES: [(ButtonId, Page, &str); 6] = [
+    ("DISPLAY", Page::Display, "Brightness, theme"),
+    ("POWER", Page::Power, "Sleep, battery"),
+    ("CLOCK", Page::Clock, "Date, time zone"),
+    ("INPUT", Page::Input, "Touch and keys"),
+    ("STORAGE", Page::Storage, "Usage, format"),
+    ("ABOUT", Page::About, "Version, crashes"),
+];
+
+// Button, key, long press and grid row of the hardware key mapping buttons.
+const KEY_BUTTONS: [(ButtonId, HwKey, bool, u16); 4] = [
+    ("BOOT_SHORT", HwKey::Boot, false, 14),
+    ("BOOT_LONG", HwKey::Boot, true, 16),
+    ("USER_SHORT", HwKey::User, false, 18),
+    ("USER_LONG", HwKey::User, true, 20),
+];
+
+// Part of the time changed by VALUE_UP and VALUE_DOWN.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+enum TimeField {
+    Year,
+    Month,
+    Day,
+    #[default]
+    Hour,
+    Minute,
 }
 
-impl Default for SettingsApp {
-    fn default() -> Self {
-        Self {
-            last_input_events: InputEvents {
-                touch: None,
-                button: None,
-            },
-            last_update: Instant::now(),
-            screen_brightness: 100,
+impl TimeField {
+    fn next(&self) -> Self {
+        match self {
+            TimeField::Year => TimeField::Month,
+            TimeField::Month => TimeField::Day,
+            TimeField::Day => TimeField::Hour,
+            TimeField::Hour => TimeField::Minute,
+            TimeField::Minute => TimeField::Year,
         }
     }
+    fn name(&self) -> &'static str {
+        match self {
+            TimeField::Year => "Year",
+            TimeField::Month => "Month",
+            TimeField::Day => "Day",
+            TimeField::Hour => "Hour",
+            TimeField::Minute => "Minute",
+        }
+    }
+    // `time` with the field moved by `step`, `None` when it leaves the supported years.
+    fn adjust(&self, time: DateTime, step: i32) -> Option<DateTime> {
+        let secs = match self {
+            TimeField::Year => return time.add_months(step * 12),
+            TimeField::Month => return time.add_months(step),
+            TimeField::Day => 86_400,
+            TimeField::Hour => 3600,
+            TimeField::Minute => 60,
+        };
+        let time = DateTime::from_timestamp(time.timestamp() + secs * step as i64);
+        time.is_valid().then_some(time)
+    }
 }
 
-impl App for SettingsApp {
-    fn init(&mut self, ctx: &mut Context) -> AppResponse {
-        ctx.grid.clear(' ', BASE03, BASE03);
+// Step of the UTC offset, in minutes.
+const OFFSET_STEP: i16 = 30;
 
-        ctx.buttons.clear();
-        ctx.buttons.register_default_buttons();
+// Choices for the timeouts, in seconds.
+const IDLE_STEPS: [u64; 6] = [5, 10, 30, 60, 120, 300];
+const SLEEP_STEPS: [u64; 6] = [30, 60, 120, 300, 600, 1800];
+// 0 never powers off.
+const OFF_STEPS: [u64; 5] = [0, 600, 1800, 3600, 7200];
+// Idle backlight, in percent of the brightness.
+const IDLE_DIM_STEPS: [u64; 5] = [10, 25, 50, 75, 100];
 
-        ctx.buttons.register_button(
-            "BRIGHTNESS_DOWN",
-            Rect {
-                x_min: 0,
-                y_min: 11 * CELL_H,
-                x_max: 15 * CELL_W,
-                y_max: 12 * CELL_H,
-            },
-        );
-        ctx.buttons.register_button(
-            "BRIGHTNESS_UP",
-            Rect {
-                x_min: 0,
-                y_min: 13 * CELL_H,
-                x_max: 15 * CELL_W,
-                y_max: 14 * CELL_H,
-            },
-        );
+// Redraws the uptime, the clock and the power mode.
+const REFRESH_TIMER: TimerId = 0;
 
-        self.screen_brightness = ctx.settings.read(|s| s.user_brightness);
+// Values are drawn right of their button.
+const VALUE_COL: u16 = 17;
+const CRASH_ROW: u16 = 9;
+const DEFAULTS_ROW: u16 = 20;
+const FORMAT_ROW: u16 = 24;
+const ERROR_ROW: u16 = 29;
 
-        AppResponse::dirty()
+#[derive(Default)]
+pub struct SettingsApp {
+    // `None` for the menu.
+    page: Option<Page>,
+    last_touch: Option<TouchEvent>,
+    // Report of the panic that caused the last restart.
+    last_crash: Option<CrashReport>,
+    // Error of the last system command, until the next one succeeds.
+    cmd_error: Option<SystemError>,
+    // DEFAULTS or FORMAT was pressed once, it has to be confirmed.
+    confirm: bool,
+    time_field: TimeField,
+}
+
+// A button on one row at the left, its value is drawn next to it.
+fn row_button(ctx: &mut Context, id: ButtonId, row: u16) {
+    let _ = ctx.buttons.register_button(
+        id,
+        Rect {
+            x_min: 0,
+            y_min: row * CELL_H,
+            x_max: 15 * CELL_W,
+            y_max: (row + 1) * CELL_H,
+        },
+    );
+}
+
+// The first step after `current` that is `valid`, wrapping around.
+fn next_step(steps: &[u64], current: u64, valid: impl Fn(u64) -> bool) -> Option<u64> {
+    let start = steps
+        .iter()
+        .position(|step| *step > current)
+        .unwrap_or(steps.len());
+    steps
+        .iter()
+        .cycle()
+        .skip(start)
+        .take(steps.len())
+        .copied()
+        .find(|step| valid(*step))
+}
+
+fn write_time(ctx: &mut Context, row: u16, secs: u64) {
+    let text = if secs < 60 {
+        heapless::format!(12; "{} s", secs)
+    } else {
+        heapless::format!(12; "{} min", secs / 60)
+    };
+    write_value(ctx, row, &text.unwrap_or_default());
+}
+
+fn write_offset(ctx: &mut Context, row: u16, zone: TimeZone) {
+    let sign = if zone.offset_min < 0 { '-' } else { '+' };
+    let offset = zone.offset_min.unsigned_abs();
+    let text = heapless::format!(12; "UTC{}{:02}:{:02}", sign, offset / 60, offset % 60);
+    write_value(ctx, row, &text.unwrap_or_default());
+}
+
+fn write_value(ctx: &mut Context, row: u16, value: &str) {
+    ctx.grid
+        .draw_box(VALUE_COL, row, ctx.grid.cols - VALUE_COL, 1, BASE03);
+    ctx.grid.write_str(VALUE_COL, row, value, BASE3, BASE03);
+}
+
+impl SettingsApp {
+    // Register the buttons of the current page.
+    fn layout(&mut self, ctx: &mut Context) {
+        ctx.grid.clear(' ', BASE03, BASE03);
+        ctx.buttons.clear();
+        ctx.buttons.register_default_buttons();
+        self.confirm = false;
+
+        match self.page {
+            None => {
+                for (idx, (id, _, _)) in PAGES.iter().enumerate() {
+                    let row = 3 + idx as u16 * 3;
+                    let _ = ctx.buttons.register_button(
+                        id,
+                        Rect {
+                            x_min: CELL_W,
+                            y_min: row * CELL_H,
+                            x_max: 15 * CELL_W,
+                            y_max: (row + 2) * CELL_H,
+                        },
+                    );
+                }
+                row_button(ctx, "DEFAULTS", DEFAULTS_ROW);
+            }
+            Some(Page::Display) => {
+                row_button(ctx, "BRIGHTNESS_DOWN", 5);
+                row_button(ctx, "BRIGHTNESS_UP", 7);
+                row_button(ctx, "THEME", 9);
+                row_button(ctx, "ROTATION", 11);
+            }
+            Some(Page::Power) => {
+                row_button(ctx, "IDLE_TIME", 5);
+                row_button(ctx, "SLEEP_TIME", 7);
+                row_button(ctx, "POWER_OFF", 9);
+                row_button(ctx, "NEVER_SLEEP", 11);
+                row_button(ctx, "PROFILE", 13);
+                row_button(ctx, "IDLE_DIM", 15);
+            }
+            Some(Page::Clock) => {
+                row_button(ctx, "FIELD", 7);
+                row_button(ctx, "VALUE_UP", 9);
+                row_button(ctx, "VALUE_DOWN", 11);
+                row_button(ctx, "OFFSET_UP", 16);
+                row_button(ctx, "OFFSET_DOWN", 18);
+                row_button(ctx, "DST", 20);
+                row_button(ctx, "KEEP_TIME", 22);
+            }
+            Some(Page::Input) => {
+                row_button(ctx, "CALIBRATE", 5);
+                row_button(ctx, "SENSITIVITY", 7);
+                for (id, _, _, row) in KEY_BUTTONS {
+                    row_button(ctx, id, row);
+                }
+            }
+            Some(Page::Storage) => row_button(ctx, "FORMAT", FORMAT_ROW),
+            Some(Page::About) => {
+                self.last_crash = crash::last_crash();
+                if self.last_crash.is_some() {
+                    let _ = ctx.buttons.register_button(
+                        "CLEAR",
+                        Rect {
+                            x_min: 34 * CELL_W,
+                            y_min: CRASH_ROW * CELL_H,
+                            x_max: 39 * CELL_W,
+                            y_max: (CRASH_ROW + 1) * CELL_H,
+                        },
+                    );
+                }
+            }
+        }
     }
-    fn update(&mut self, input: InputEvents, _ctx: &mut Context) -> AppResponse {
-        if let Some(ButtonEvent::Up(id)) = input.button {
-            if id == "BRIGHTNESS_UP" {
-                if self.screen_brightness <= 90 {
-                    self.screen_brightness += 10;
+    fn update_page(&mut self, page: Page, id: ButtonId, ctx: &mut Context) -> AppResponse {
+        let cmd = match (page, id) {
+            (Page::Display, "BRIGHTNESS_UP" | "BRIGHTNESS_DOWN") => {
+                let brightness = ctx.settings.read(|s| s.user_brightness);
+                let brightness = if id == "BRIGHTNESS_UP" {
+                    (brightness + 10).min(100)
+                } else {
+                    brightness.saturating_sub(10).max(10)
+                };
+                SystemCmd::SetBrightness(brightness)
+            }
+            (Page::Display, "THEME") => SystemCmd::SetTheme(ctx.settings.read(|s| s.theme).next()),
+            (Page::Display, "ROTATION") => {
+                SystemCmd::SetRotation(ctx.settings.read(|s| s.rotation).next())
+            }
+            (Page::Power, "IDLE_TIME") => {
+                let (idle, sleep) = ctx.settings.read(|s| (s.idle_time, s.sleep_time));
+                match next_step(&IDLE_STEPS, idle, |step| step < sleep) {
+                    Some(idle) => SystemCmd::SetIdleTimeout(idle),
+                    None => return AppResponse::none(),
+                }
+            }
+            (Page::Power, "SLEEP_TIME") => {
+                let (idle, sleep, off) = ctx
+                    .settings
+                    .read(|s| (s.idle_time, s.sleep_time, s.off_time));
+                match next_step(&SLEEP_STEPS, sleep, |step| {
+                    step > idle && (off == 0 || step < off)
+                }) {
+                    Some(sleep) => SystemCmd::SetSleepTimeout(sleep),
+                    None => return AppResponse::none(),
+                }
+            }
+            (Page::Power, "POWER_OFF") => {
+                let (sleep, off) = ctx.settings.read(|s| (s.sleep_time, s.off_time));
+                match next_step(&OFF_STEPS, off, |step| step == 0 || step > sleep) {
+                    Some(off) => SystemCmd::SetPowerOffTimeout(off),
+                    None => return AppResponse::none(),
+                }
+            }
+            (Page::Power, "NEVER_SLEEP") => {
+                SystemCmd::SetNeverSleep(!ctx.settings.read(|s| s.never_sleep))
+            }
+            (Page::Power, "PROFILE") => {
+                SystemCmd::SetPowerProfile(ctx.settings.read(|s| s.power_profile).next())
+            }
+            (Page::Power, "IDLE_DIM") => {
+                let dim = ctx.settings.read(|s| s.idle_brightness) as u64;
+                match next_step(&IDLE_DIM_STEPS, dim, |_| true) {
+                    Some(dim) => SystemCmd::SetIdleBrightness(dim as u8),
+                    None => return AppResponse::none(),
                 }
-                return AppResponse::system(SystemCmd::SetBrightness(self.screen_brightness));
             }
-            if id == "BRIGHTNESS_DOWN" {
-                if self.screen_brightness > 10 {
-                    self.screen_brightness -= 10;
+            (Page::Clock, "FIELD") => {
+                self.time_field = self.time_field.next();
+                return AppResponse::dirty();
+            }
+            (Page::Clock, "VALUE_UP" | "VALUE_DOWN") => {
+                let zone = ctx.settings.read(|s| s.time_zone);
+                // A clock that was never set starts at the beginning of the year.
+                let Some(time) =
+                    clock::local_now(zone).or_else(|| DateTime::new(2026, 1, 1, 0, 0, 0))
+                else {
+                    return AppResponse::none();
+                };
+                let step = if id == "VALUE_UP" { 1 } else { -1 };
+                match self.time_field.adjust(time, step) {
+                    Some(time) => SystemCmd::SetTime(time),
+                    None => return AppResponse::none(),
                 }
-                return AppResponse::system(SystemCmd::SetBrightness(self.screen_brightness));
             }
+            (Page::Clock, "OFFSET_UP" | "OFFSET_DOWN") => {
+                let mut zone = ctx.settings.read(|s| s.time_zone);
+                zone.offset_min = if id == "OFFSET_UP" {
+                    (zone.offset_min + OFFSET_STEP).min(TimeZone::MAX_OFFSET)
+                } else {
+                    (zone.offset_min - OFFSET_STEP).max(TimeZone::MIN_OFFSET)
+                };
+                SystemCmd::SetTimeZone(zone)
+            }
+            (Page::Clock, "DST") => {
+                let mut zone = ctx.settings.read(|s| s.time_zone);
+                zone.dst = zone.dst.next();
+                SystemCmd::SetTimeZone(zone)
+            }
+            (Page::Clock, "KEEP_TIME") => {
+                SystemCmd::SetKeepTime(!ctx.settings.read(|s| s.keep_time))
+            }
+            (Page::Input, "CALIBRATE") => SystemCmd::StartCalibration,
+            (Page::Input, "SENSITIVITY") => {
+                SystemCmd::SetTouchSensitivity(ctx.settings.read(|s| s.touch_sensitivity).next())
+            }
+            (Page::Input, _) => {
+                let Some((_, key, long, _)) = KEY_BUTTONS.iter().find(|b| b.0 == id) else {
+                    return AppResponse::none();
+                };
+                let mut mapping = ctx.settings.read(|s| s.key_mapping(*key));
+                if *long {
+                    mapping.long = mapping.long.next();
+                } else {
+                    mapping.short = mapping.short.next();
+                }
+                SystemCmd::SetKeyMapping(*key, mapping)
+            }
+            (Page::Storage, "FORMAT") => {
+                // Ask again before erasing the files, with a button in the same place.
+                self.confirm = true;
+                ctx.buttons.remove_button("FORMAT");
+                row_button(ctx, "CONFIRM", FORMAT_ROW);
+                return AppResponse::dirty();
+            }
+            (Page::Storage, "CONFIRM") => {
+                self.confirm = false;
+                ctx.buttons.remove_button("CONFIRM");
+                row_button(ctx, "FORMAT", FORMAT_ROW);
+                SystemCmd::FormatStorage
+            }
+            (Page::About, "CLEAR") => {
+                crash::clear_last_crash();
+                self.last_crash = None;
+                ctx.buttons.remove_button("CLEAR");
+                ctx.grid.draw_box(0, CRASH_ROW, 40, 5, BASE03);
+                return AppResponse::dirty();
+            }
+            _ => return AppResponse::none(),
         };
+        AppResponse::dirty().with_system(cmd)
+    }
 
-        if self.last_input_events != input {
-            self.last_input_events = input;
-            self.last_update = Instant::now();
-            return AppResponse::dirty();
+    fn render_menu(&mut self, ctx: &mut Context) {
+        for (idx, (_, _, description)) in PAGES.iter().enumerate() {
+            let row = 3 + idx as u16 * 3;
+            ctx.grid
+                .write_str(VALUE_COL, row, description, BASE1, BASE03);
         }
-        if self.last_update.elapsed() >= Duration::from_secs(1) {
-            self.last_update = Instant::now();
-            return AppResponse::dirty();
+        if self.confirm {
+            write_value(ctx, DEFAULTS_ROW, "Resets every system setting");
+        } else {
+            ctx.grid
+                .write_str(VALUE_COL, DEFAULTS_ROW, "Reset all settings", BASE1, BASE03);
         }
-        AppResponse::none()
     }
-    fn render(&mut self, ctx: &mut Context) {
-        ctx.grid.write_str(0, 3, "> ABOUT <", BASE3, BASE02);
+    fn render_display(&mut self, ctx: &mut Context) {
+        ctx.grid.write_str(0, 3, "> DISPLAY <", BASE3, BASE02);
         ctx.grid.write_str(
             0,
-            4,
-            &heapless::format!(48; "V: {} ({})", env!("CARGO_PKG_VERSION"), GIT_HASH)
+            6,
+            &heapless::format!(32; "Brightness: {:03}", ctx.settings.read(|s| s.user_brightness))
                 .unwrap_or_default(),
             BASE3,
             BASE03,
         );
-        let mut epoch_time = Instant::now().duration_since_epoch().as_secs();
-        let hours = {
-            let val = epoch_time / 3600;
-            epoch_time = epoch_time % 3600;
-            val
-        };
-        let minutes = {
-            let val = epoch_time / 60;
-            epoch_time = epoch_time % 60;
-            val
+        write_value(ctx, 9, ctx.settings.read(|s| s.theme).name());
+        write_value(ctx, 11, ctx.settings.read(|s| s.rotation).name());
+    }
+    fn render_power(&mut self, ctx: &mut Context) {
+        ctx.grid.write_str(0, 3, "> POWER <", BASE3, BASE02);
+        write_time(ctx, 5, ctx.settings.read(|s| s.idle_time));
+        write_time(ctx, 7, ctx.settings.read(|s| s.sleep_time));
+        match ctx.settings.read(|s| s.off_time) {
+            0 => write_value(ctx, 9, "Never"),
+            off => write_time(ctx, 9, off),
+        }
+        let never_sleep = if ctx.settings.read(|s| s.never_sleep) {
+            "On"
+        } else {
+            "Off"
         };
-        let seconds = epoch_time;
+        write_value(ctx, 11, never_sleep);
+        write_value(ctx, 13, ctx.settings.read(|s| s.power_profile).name());
+        let idle_dim = heapless::format!(12; "{}%", ctx.settings.read(|s| s.idle_brightness));
+        write_value(ctx, 15, &idle_dim.unwrap_or_default());
+
+        ctx.grid.draw_box(0, 18, 40, 4, BASE03);
         ctx.grid.write_str(
             0,
-            5,
-            &heapless::format!(128; "Uptime: {:02}:{:02}:{:02}",hours, minutes, seconds)
+            18,
+            &heapless::format!(40; "Mode: {:?}", power_mode()).unwrap_or_default(),
+            BASE3,
+            BASE03,
+        );
+        ctx.grid.write_str(
+            0,
+            19,
+            &heapless::format!(40; "Backlight: {:03}", ctx.settings.read(|s| s.effective_brightness))
                 .unwrap_or_default(),
             BASE3,
             BASE03,
         );
+        let battery = match battery_state() {
+            Some(state) => heapless::format!(
+                40;
+                "Battery: {}.{:02} V, {}%{}",
+                state.millivolts / 1000,
+                state.millivolts % 1000 / 10,
+                state.percent,
+                if state.charging { ", charging" } else { "" }
+            ),
+            None => heapless::format!(40; "Battery: none"),
+        };
+        ctx.grid
+            .write_str(0, 20, &battery.unwrap_or_default(), BASE3, BASE03);
+        let locks = wake_locks();
         ctx.grid.write_str(
             0,
-            6,
-            &heapless::format!(64; "Cpu: {}", esp_hal::chip!()).unwrap_or_default(),
+            21,
+            &heapless::format!(40; "Wake locks: {} screen, {} cpu", locks.screen, locks.cpu)
+                .unwrap_or_default(),
             BASE3,
             BASE03,
         );
+    }
+    fn render_clock(&mut self, ctx: &mut Context) {
+        ctx.grid.write_str(0, 3, "> CLOCK <", BASE3, BASE02);
+        let zone = ctx.settings.read(|s| s.time_zone);
+        let now = match clock::local_now(zone) {
+            Some(time) => heapless::format!(40; "{} {}", time.weekday_name(), time),
+            None => heapless::format!(40; "Not set"),
+        };
+        ctx.grid.draw_box(0, 5, 40, 1, BASE03);
+        ctx.grid
+            .write_str(0, 5, &now.unwrap_or_default(), BASE3, BASE03);
+        write_value(ctx, 7, self.time_field.name());
 
-        ctx.grid.write_str(0, 8, "> DEBUG <", BASE3, BASE02);
-        let touch = if let Some(touch) = &self.last_input_events.touch {
-            match touch {
-                crate::touch::TouchEvent::Down { x, y } => {
-                    &heapless::format!(128; "Down (x: {}, y: {})", x, y).unwrap_or_default()
-                }
-                crate::touch::TouchEvent::Move { x, y } => {
-                    &heapless::format!(128; "Move (x: {}, y: {})", x, y).unwrap_or_default()
-                }
-                crate::touch::TouchEvent::Up => "Up",
-            }
+        ctx.grid.write_str(0, 14, "> TIME ZONE <", BASE3, BASE02);
+        let dst_now = clock::now_utc().is_some_and(|utc| zone.is_dst(utc));
+        write_value(ctx, 14, if dst_now { "Summer time" } else { "" });
+        write_offset(ctx, 16, zone);
+        write_value(ctx, 20, zone.dst.name());
+        let keep_time = if ctx.settings.read(|s| s.keep_time) {
+            "Kept across resets"
         } else {
-            "None"
+            "Lost on reset"
+        };
+        write_value(ctx, 22, keep_time);
+    }
+    fn render_input(&mut self, ctx: &mut Context) {
+        ctx.grid.write_str(0, 3, "> TOUCH <", BASE3, BASE02);
+        write_value(ctx, 7, ctx.settings.read(|s| s.touch_sensitivity).name());
+        let touch = match &self.last_touch {
+            Some(TouchEvent::Down { x, y }) => heapless::format!(40; "Down (x: {}, y: {})", x, y),
+            Some(TouchEvent::Move { x, y }) => heapless::format!(40; "Move (x: {}, y: {})", x, y),
+            Some(TouchEvent::Up) => heapless::format!(40; "Up"),
+            None => heapless::format!(40; "None"),
+        };
+        ctx.grid.draw_box(0, 9, 40, 1, BASE03);
+        ctx.grid
+            .write_str(0, 9, &touch.unwrap_or_default(), BASE3, BASE03);
+
+        ctx.grid.write_str(0, 12, "> KEYS <", BASE3, BASE02);
+        for (_, key, long, row) in KEY_BUTTONS {
+            let mapping = ctx.settings.read(|s| s.key_mapping(key));
+            let action = if long { mapping.long } else { mapping.short };
+            write_value(ctx, row, action.name());
+        }
+    }
+    fn render_storage(&mut self, ctx: &mut Context) {
+        ctx.grid.write_str(0, 3, "> STORAGE <", BASE3, BASE02);
+        let disk = match ctx.storage.disk_usage() {
+            Some((used, total)) => {
+                heapless::format!(40; "Flash: {}/{} KB", used.div_ceil(1024), total / 1024)
+            }
+            None => heapless::format!(40; "RAM only, lost on restart"),
         };
-        ctx.grid.write_str(0, 9, touch, BASE3, BASE03);
+        ctx.grid.draw_box(0, 4, 40, 1, BASE03);
+        ctx.grid
+            .write_str(0, 4, &disk.unwrap_or_default(), BASE3, BASE03);
+
+        ctx.grid.write_str(0, 6, "> FILES <", BASE3, BASE02);
+        let namespaces =
+            core::iter::once(SYSTEM_NAMESPACE).chain(APPS.iter().map(|info| info.name));
+        for (idx, namespace) in namespaces.enumerate() {
+            let usage = ctx
+                .storage
+                .with_namespace(namespace, |storage| storage.usage());
+            let row = 7 + idx as u16;
+            if row >= FORMAT_ROW - 1 {
+                break;
+            }
+            ctx.grid.write_str(0, row, namespace, BASE1, BASE03);
+            write_value(
+                ctx,
+                row,
+                &heapless::format!(16; "{} B", usage).unwrap_or_default(),
+            );
+        }
 
+        let note = if self.confirm {
+            "Erases the files of every app"
+        } else {
+            ""
+        };
+        write_value(ctx, FORMAT_ROW, note);
+    }
+    fn render_about(&mut self, ctx: &mut Context) {
+        ctx.grid.write_str(0, 3, "> ABOUT <", BASE3, BASE02);
+        ctx.grid.write_str(
+            0,
+            4,
+            &heapless::format!(48; "V: {} ({})", env!("CARGO_PKG_VERSION"), GIT_HASH)
+                .unwrap_or_default(),
+            BASE3,
+            BASE03,
+        );
+        let uptime = Instant::now().duration_since_epoch().as_secs();
         ctx.grid.write_str(
             0,
-            12,
-            &heapless::format!(32; "Brightness: {:03}", ctx.settings.read(|s| s.effective_brightness))
+            5,
+            &heapless::format!(128; "Uptime: {:02}:{:02}:{:02}", uptime / 3600, uptime % 3600 / 60, uptime % 60)
                 .unwrap_or_default(),
             BASE3,
             BASE03,
         );
+        ctx.grid.write_str(
+            0,
+            6,
+            &heapless::format!(64; "Cpu: {}, {} MHz base", esp_hal::chip!(), cpu::base_speed().mhz())
+                .unwrap_or_default(),
+            BASE3,
+            BASE03,
+        );
+
+        ctx.grid
+            .write_str(0, CRASH_ROW, "> LAST CRASH <", BASE3, BASE02);
+        match &self.last_crash {
+            Some(report) => {
+                ctx.grid.write_str(
+                    0,
+                    CRASH_ROW + 1,
+                    &heapless::format!(40; "{} after {}s", report.app, report.uptime_ms / 1000)
+                        .unwrap_or_default(),
+                    BASE3,
+                    BASE03,
+                );
+                ctx.grid
+                    .write_str(0, CRASH_ROW + 2, &report.location, BASE1, BASE03);
+                // The message takes up to two rows.
+                let second_row = report
+                    .message
+                    .char_indices()
+                    .nth(ctx.grid.cols as usize)
+                    .map_or("", |(idx, _)| &report.message[idx..]);
+                ctx.grid
+                    .write_str(0, CRASH_ROW + 3, &report.message, RED, BASE03);
+                ctx.grid
+                    .write_str(0, CRASH_ROW + 4, second_row, RED, BASE03);
+            }
+            None => ctx.grid.write_str(0, CRASH_ROW + 1, "None", BASE3, BASE03),
+        }
+    }
+}
+
+impl App for SettingsApp {
+    fn init(&mut self, ctx: &mut Context) -> AppResponse {
+        self.page = None;
+        self.cmd_error = None;
+        self.layout(ctx);
+        ctx.timers
+            .start_repeating(REFRESH_TIMER, Duration::from_secs(1));
+
+        AppResponse::dirty()
+    }
+    fn update(&mut self, input: InputEvents, ctx: &mut Context) -> AppResponse {
+        if let Some(result) = input.system {
+            self.cmd_error = result.err();
+            return AppResponse::dirty();
+        }
+
+        if let Some(ButtonEvent::Up(id)) = input.button {
+            let Some(page) = self.page else {
+                // Ask again before resetting, like FORMAT.
+                if id == "DEFAULTS" {
+                    self.confirm = true;
+                    ctx.buttons.remove_button("DEFAULTS");
+                    row_button(ctx, "CONFIRM", DEFAULTS_ROW);
+                    return AppResponse::dirty();
+                }
+                if id == "CONFIRM" {
+                    self.confirm = false;
+                    ctx.buttons.remove_button("CONFIRM");
+                    row_button(ctx, "DEFAULTS", DEFAULTS_ROW);
+                    return AppResponse::dirty().with_system(SystemCmd::ResetSettings);
+                }
+                if let Some((id, page, _)) = PAGES.iter().find(|p| p.0 == id) {
+                    self.page = Some(*page);
+                    self.layout(ctx);
+                    return AppResponse::push_screen(id);
+                }
+                return AppResponse::none();
+            };
+            return self.update_page(page, id, ctx);
+        };
+
+        if self.last_touch != input.touch {
+            self.last_touch = input.touch;
+            if self.page == Some(Page::Input) {
+                return AppResponse::dirty();
+            }
+        }
+        if input.timer == Some(REFRESH_TIMER)
+            && matches!(self.page, Some(Page::Power | Page::Clock | Page::About))
+        {
+            return AppResponse::dirty();
+        }
+        AppResponse::none()
+    }
+    fn render(&mut self, ctx: &mut Context) {
+        match self.page {
+            None => self.render_menu(ctx),
+            Some(Page::Display) => self.render_display(ctx),
+            Some(Page::Power) => self.render_power(ctx),
+            Some(Page::Clock) => self.render_clock(ctx),
+            Some(Page::Input) => self.render_input(ctx),
+            Some(Page::Storage) => self.render_storage(ctx),
+            Some(Page::About) => self.render_about(ctx),
+        }
+
+        ctx.grid.draw_box(0, ERROR_ROW, 40, 1, BASE03);
+        if let Some(err) = self.cmd_error {
+            ctx.grid.write_str(
+                0,
+                ERROR_ROW,
+                &heapless::format!(40; "Failed: {:?}", err).unwrap_or_default(),
+                RED,
+                BASE03,
+            );
+        }
     }
     fn get_name(&self) -> &'static str {
         "SETTINGS"
     }
+    // The calibration screen resumes the app on the page it was started from.
+    fn on_resume(&mut self, ctx: &mut Context) -> AppResponse {
+        self.layout(ctx);
+        ctx.timers
+            .start_repeating(REFRESH_TIMER, Duration::from_secs(1));
+        AppResponse::dirty()
+    }
+    fn on_close_screen(&mut self, _screen: &'static str, ctx: &mut Context) -> AppResponse {
+        self.page = None;
+        self.layout(ctx);
+        AppResponse::dirty()
+    }
 }
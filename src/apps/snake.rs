@@ -1,6 +1,8 @@
 use crate::{
     apps::app::{App, AppResponse, Context, InputEvents},
     graphics::*,
+    power::{WakeLock, WakeLockKind, wake_lock},
+    timers::TimerId,
     touch::TouchEvent,
 };
 use esp_hal::time::{Duration, Instant};
@@ -13,6 +15,11 @@ pub const FIELD_MIN_Y: u16 = 4;
 pub const FIELD_MAX_X: u16 = 38;
 pub const FIELD_MAX_Y: u16 = 29;
 
+const HIGH_SCORE_KEY: &str = "highscore";
+
+const TICK_TIMER: TimerId = 0;
+const TICK_TIME: Duration = Duration::from_millis(200);
+
 enum Direction {
     North,
     East,
@@ -43,11 +50,14 @@ impl Direction {
 enum GameState {
     Start,
     Playing,
+    // Set when switching away mid-game, continues on the next tap.
+    Paused,
+    // BACK was pressed mid-game, pressing it again quits.
+    ConfirmQuit,
     Dead,
 }
 
 pub struct SnakeApp {
-    last_update: Instant,
     snake: [(u16, u16); MAX_LENGTH],
     length: u16,
     score: u16,
@@ -56,12 +66,13 @@ pub struct SnakeApp {
     dir: Direction,
     state: GameState,
     food_pos: (u16, u16),
+    // Keeps the screen on while the snake moves.
+    wake_lock: Option<WakeLock>,
 }
 
 impl Default for SnakeApp {
     fn default() -> Self {
         Self {
-            last_update: Instant::now(),
             snake: [(0, 0); MAX_LENGTH],
             length: 0,
             score: 0,
@@ -70,18 +81,27 @@ impl Default for SnakeApp {
             dir: Direction::East,
             state: GameState::Start,
             food_pos: (0, 0),
+            wake_lock: None,
         }
     }
 }
 
 impl SnakeApp {
+    fn set_state(&mut self, state: GameState) {
+        let playing = state == GameState::Playing;
+        if playing != self.wake_lock.is_some() {
+            self.wake_lock = playing.then(|| wake_lock(WakeLockKind::Screen, "SNAKE"));
+        }
+        self.state = state;
+    }
+
     fn reset_game(&mut self) {
         self.snake[0] = (10, 10);
         self.length = 1;
         self.dir = Direction::East;
 
         self.score = 0;
-        self.state = GameState::Playing;
+        self.set_state(GameState::Playing);
 
         self.update_food_pos();
     }
@@ -95,6 +115,12 @@ impl SnakeApp {
         }
     }
 
+    fn draw_snake(&self, ctx: &mut Context) {
+        for pos in self.snake.iter().take(self.length as usize) {
+            ctx.grid.put_char(pos.0, pos.1, ' ', BLUE, BLUE);
+        }
+    }
+
     fn update_position(&self, old_pos: (u16, u16), dir: &Direction) -> (u16, u16) {
         let mut new_pos = old_pos;
         match dir {
@@ -140,14 +166,22 @@ impl SnakeApp {
         // Use time since startup for random.
         let rand = Instant::now().duration_since_epoch().as_millis();
 
-        self.food_pos = (
-            FIELD_MIN_X + rand as u16 % (FIELD_MAX_X - FIELD_MIN_X),
-            FIELD_MIN_Y + (rand as u16 / 2) % (FIELD_MAX_Y - FIELD_MIN_Y),
-        );
-
-        for snake_pos in self.snake.iter().take(self.length as usize) {
-            if *snake_pos == self.food_pos {
-                return self.update_food_pos();
+        let width = FIELD_MAX_X - FIELD_MIN_X;
+        let cells = width * (FIELD_MAX_Y - FIELD_MIN_Y);
+        let start = (rand % cells as u64) as u16;
+
+        // Take the first free cell after a random one, so this also ends when the field is full.
+        for offset in 0..cells {
+            let idx = (start + offset) % cells;
+            let pos = (FIELD_MIN_X + idx % width, FIELD_MIN_Y + idx / width);
+            if !self
+                .snake
+                .iter()
+                .take(self.length as usize)
+                .any(|p| *p == pos)
+            {
+                self.food_pos = pos;
+                return;
             }
         }
     }
@@ -160,18 +194,18 @@ impl App for SnakeApp {
         ctx.buttons.clear();
         ctx.buttons.register_default_buttons();
 
-        self.high_score = if let Some(raw_high_score) = ctx.fs.read("snake_highscore") {
-            ((raw_high_score[0] as u16) << 8) | raw_high_score[1] as u16
-        } else {
-            0
-        };
+        self.high_score = ctx.storage.get_u16(HIGH_SCORE_KEY).unwrap_or(0);
 
         self.draw_field(ctx);
+        ctx.timers.start_repeating(TICK_TIMER, TICK_TIME);
 
         AppResponse::dirty()
     }
     fn update(&mut self, input: InputEvents, ctx: &mut Context) -> AppResponse {
-        if let Some(TouchEvent::Down { x, y: _ }) = input.touch {
+        // A touch on a button like BACK is not meant for the game.
+        if let Some(TouchEvent::Down { x, y: _ }) = input.touch
+            && input.button.is_none()
+        {
             // Reset Game
             if self.state == GameState::Dead || self.state == GameState::Start {
                 self.reset_game();
@@ -180,6 +214,14 @@ impl App for SnakeApp {
                 return AppResponse::dirty();
             }
 
+            if matches!(self.state, GameState::Paused | GameState::ConfirmQuit) {
+                self.set_state(GameState::Playing);
+                ctx.grid.clear(' ', BASE03, BASE03);
+                self.draw_field(ctx);
+                self.draw_snake(ctx);
+                return AppResponse::dirty();
+            }
+
             if !self.dir_changed {
                 if x < SCREEN_W / 2 {
                     self.dir = self.dir.left()
@@ -190,9 +232,7 @@ impl App for SnakeApp {
             }
         }
 
-        if self.last_update.elapsed() > Duration::from_millis(200)
-            && self.state == GameState::Playing
-        {
+        if input.timer == Some(TICK_TIMER) && self.state == GameState::Playing {
             let mut increase_score = false;
             self.dir_changed = false;
 
@@ -224,15 +264,13 @@ impl App for SnakeApp {
                 if self.score > self.high_score {
                     self.high_score = self.score;
 
-                    let res = ctx
-                        .fs
-                        .write("snake_highscore", &self.high_score.to_be_bytes());
+                    let res = ctx.storage.put_u16(HIGH_SCORE_KEY, self.high_score);
 
                     if res.is_err() {
                         error!("Failed to save highscore..");
                     }
                 }
-                self.state = GameState::Dead;
+                self.set_state(GameState::Dead);
             }
 
             if increase_score {
@@ -246,7 +284,6 @@ impl App for SnakeApp {
                 self.update_food_pos();
             }
 
-            self.last_update = Instant::now();
             return AppResponse::dirty();
         }
         AppResponse::none()
@@ -276,6 +313,15 @@ impl App for SnakeApp {
                 ctx.grid.center_str(14, "<- LEFT | RIGHT ->", BASE1, BASE01);
                 ctx.grid.center_str(16, "Tap to start", BASE3, BASE01);
             }
+            GameState::Paused => {
+                ctx.grid.center_str(14, "PAUSED", BASE3, BLUE);
+                ctx.grid.center_str(16, "Tap to continue", BASE3, BASE01);
+            }
+            GameState::ConfirmQuit => {
+                ctx.grid.center_str(14, "QUIT GAME?", BASE3, RED);
+                ctx.grid
+                    .center_str(16, "BACK to quit, tap to continue", BASE3, BASE01);
+            }
             GameState::Dead => {
                 ctx.grid.center_str(14, "GAME OVER!", BASE3, RED);
                 ctx.grid.center_str(16, "Tap to reset", BASE3, BASE01);
@@ -286,4 +332,24 @@ impl App for SnakeApp {
     fn get_name(&self) -> &'static str {
         "SNAKE"
     }
+    fn keep_alive(&self) -> bool {
+        true
+    }
+    fn on_suspend(&mut self, _ctx: &mut Context) {
+        if matches!(self.state, GameState::Playing | GameState::ConfirmQuit) {
+            self.set_state(GameState::Paused);
+        }
+    }
+    fn on_resume(&mut self, ctx: &mut Context) -> AppResponse {
+        let response = self.init(ctx);
+        self.draw_snake(ctx);
+        response
+    }
+    fn on_back(&mut self, _ctx: &mut Context) -> Option<AppResponse> {
+        if self.state == GameState::Playing {
+            self.set_state(GameState::ConfirmQuit);
+            return Some(AppResponse::dirty());
+        }
+        None
+    }
 }
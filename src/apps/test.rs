@@ -1,16 +1,27 @@
-use esp_hal::time::{Duration, Instant};
+use embedded_graphics::pixelcolor::Rgb565;
+use esp_hal::time::Duration;
 use log::info;
 
 use crate::{
-    apps::app::{App, AppResponse, Context, InputEvents},
+    apps::app::{
+        App, AppID, AppResponse, Context, InputEvents, Intent, IntentData, IntentResult, RequestId,
+    },
     graphics::*,
+    input::{ButtonEvent, Rect},
+    keyboard::{KeyEvent, Layout, TextBuffer},
+    system::SystemCmd,
+    timers::TimerId,
     touch::TouchEvent,
 };
 
+const FLICKER_TIMER: TimerId = 0;
+const PICK_COLOR: RequestId = 0;
+
 pub struct TestApp {
     flicker: bool,
     count: u16,
-    last_update: Instant,
+    text: TextBuffer,
+    text_color: Rgb565,
 }
 
 impl Default for TestApp {
@@ -18,7 +29,8 @@ impl Default for TestApp {
         Self {
             flicker: false,
             count: 0,
-            last_update: Instant::now(),
+            text: TextBuffer::new(),
+            text_color: YELLOW,
         }
     }
 }
@@ -29,12 +41,56 @@ impl App for TestApp {
 
         ctx.buttons.clear();
         ctx.buttons.register_default_buttons();
+        let _ = ctx.buttons.register_button(
+            "TYPE",
+            Rect {
+                x_min: 200,
+                y_min: 0,
+                x_max: 239,
+                y_max: 20,
+            },
+        );
+        let _ = ctx.buttons.register_button(
+            "COLOR",
+            Rect {
+                x_min: 200,
+                y_min: 24,
+                x_max: 239,
+                y_max: 44,
+            },
+        );
+        ctx.timers
+            .start_repeating(FLICKER_TIMER, Duration::from_millis(200));
 
         AppResponse::dirty()
     }
     fn update(&mut self, input: InputEvents, ctx: &mut Context) -> AppResponse {
         let mut dirty = false;
 
+        if let Some(ButtonEvent::Up("TYPE")) = input.button {
+            return AppResponse::system(SystemCmd::OpenKeyboard("Type something:", Layout::Lower));
+        }
+        if let Some(ButtonEvent::Up("COLOR")) = input.button {
+            return AppResponse::start(Intent {
+                app: AppID::ColorPicker,
+                request: PICK_COLOR,
+                data: IntentData::Color(self.text_color),
+            });
+        }
+        if let Some(IntentResult {
+            request: PICK_COLOR,
+            data: IntentData::Color(color),
+        }) = input.result
+        {
+            self.text_color = color;
+            dirty = true;
+        }
+        if let Some(KeyEvent::Submit(text)) = input.key {
+            info!("Typed: {}", text);
+            self.text = text;
+            dirty = true;
+        }
+
         if let Some(event) = input.touch {
             match event {
                 TouchEvent::Down { x, y } | TouchEvent::Move { x, y } => {
@@ -48,10 +104,9 @@ impl App for TestApp {
             dirty = true;
         }
 
-        if self.last_update.elapsed() > Duration::from_millis(200) {
+        if input.timer == Some(FLICKER_TIMER) {
             self.flicker = !self.flicker;
             self.count += 1;
-            self.last_update = Instant::now();
             dirty = true;
         }
 
@@ -74,6 +129,10 @@ impl App for TestApp {
             self.count = 0;
             ctx.grid.clear(' ', BASE03, BASE03);
         }
+        if !self.text.is_empty() {
+            ctx.grid
+                .write_str(0, 12, &self.text, self.text_color, BASE03);
+        }
     }
     fn get_name(&self) -> &'static str {
         "TEST"
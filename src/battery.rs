@@ -0,0 +1,352 @@
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Timer};
+use heapless::Deque;
+use log::info;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+// Number of samples in the moving average.
+const WINDOW: usize = 16;
+
+// The trend is checked once a minute, a charging battery rises by more than this.
+const TREND_SAMPLES: u32 = 30;
+const TREND_MV: u16 = 8;
+
+// The percentage has to rise this far above the low level before warning again.
+const LOW_HYSTERESIS: u8 = 5;
+
+// Typical single cell LiPo under a light load, from full to empty.
+const LIPO_CURVE: [(u16, u8); 11] = [
+    (4200, 100),
+    (4100, 90),
+    (4000, 78),
+    (3900, 64),
+    (3800, 48),
+    (3750, 38),
+    (3700, 26),
+    (3650, 15),
+    (3600, 8),
+    (3500, 3),
+    (3300, 0),
+];
+
+/// Source of battery voltage readings, the ADC on the device or a mock on the host.
+pub trait VoltageSensor {
+    /// Battery voltage in millivolts.
+    fn read_mv(&mut self) -> u16;
+}
+
+pub struct BatteryConfig {
+    // Voltage and charge in percent, sorted from full to empty.
+    pub curve: &'static [(u16, u8)],
+    // Warn once the charge drops to this percentage.
+    pub low_percent: u8,
+    // Power down at this percentage.
+    pub shutdown_percent: u8,
+    // Above this voltage the battery can only be charging.
+    pub charging_mv: u16,
+    // Below this voltage there is no battery, the device runs from USB.
+    pub absent_mv: u16,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            curve: &LIPO_CURVE,
+            low_percent: 15,
+            shutdown_percent: 3,
+            charging_mv: 4250,
+            absent_mv: 2500,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct BatteryState {
+    pub millivolts: u16,
+    pub percent: u8,
+    pub charging: bool,
+    pub low: bool,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BatteryEvent {
+    // The charge dropped to the low level, with the percentage.
+    Low(u8),
+    // The battery is empty, the device should power down.
+    Empty,
+}
+
+static STATE: Mutex<Cell<Option<BatteryState>>> = Mutex::new(Cell::new(None));
+
+/// The battery state, as last measured by the battery task. `None` without a battery.
+pub fn battery_state() -> Option<BatteryState> {
+    critical_section::with(|cs| STATE.borrow(cs).get())
+}
+
+/// Estimated charge for a voltage, interpolated between the points of the curve.
+pub fn estimate_percent(curve: &[(u16, u8)], mv: u16) -> u8 {
+    let (Some(full), Some(empty)) = (curve.first(), curve.last()) else {
+        return 0;
+    };
+    if mv >= full.0 {
+        return full.1;
+    }
+    if mv <= empty.0 {
+        return empty.1;
+    }
+    for pair in curve.windows(2) {
+        let ((high_mv, high), (low_mv, low)) = (pair[0], pair[1]);
+        if mv >= low_mv {
+            // A curve that is not sorted gives a wrong estimate, but must not overflow.
+            let span = high_mv.saturating_sub(low_mv).max(1) as i32;
+            let above = ((mv - low_mv) as i32).min(span);
+            let percent = low as i32 + (high as i32 - low as i32) * above / span;
+            return percent.clamp(0, 100) as u8;
+        }
+    }
+    empty.1
+}
+
+/// Averages the battery voltage and turns it into a charge estimate.
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    samples: Deque<u16, WINDOW>,
+    // Average at the last trend check.
+    trend_mv: Option<u16>,
+    trend_count: u32,
+    charging: bool,
+    // The low warning and the shutdown were sent, until the battery is charged again.
+    warned: bool,
+    emptied: bool,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            samples: Deque::new(),
+            trend_mv: None,
+            trend_count: 0,
+            charging: false,
+            warned: false,
+            emptied: false,
+        }
+    }
+    fn average(&self) -> Option<u16> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let sum: u32 = self.samples.iter().map(|mv| *mv as u32).sum();
+        Some((sum / self.samples.len() as u32) as u16)
+    }
+    /// The averaged state, `None` before the first sample or without a battery.
+    pub fn state(&self) -> Option<BatteryState> {
+        let mv = self.average()?;
+        if mv < self.config.absent_mv {
+            return None;
+        }
+        let percent = estimate_percent(self.config.curve, mv);
+        Some(BatteryState {
+            millivolts: mv,
+            percent,
+            charging: self.charging,
+            low: percent <= self.config.low_percent && !self.charging,
+        })
+    }
+    /// Add a sample, returns the warning or shutdown it caused.
+    pub fn update(&mut self, mv: u16) -> Option<BatteryEvent> {
+        if self.samples.is_full() {
+            self.samples.pop_front();
+        }
+        let _ = self.samples.push_back(mv);
+        let average = self.average()?;
+
+        self.trend_count += 1;
+        if average >= self.config.charging_mv {
+            self.charging = true;
+            // Once the voltage drops below the limit again, the trend is compared with the charging voltage.
+            self.trend_count = 0;
+            self.trend_mv = Some(average);
+        } else if self.trend_count >= TREND_SAMPLES {
+            self.trend_count = 0;
+            if let Some(previous) = self.trend_mv {
+                self.charging = average > previous + TREND_MV;
+            }
+            self.trend_mv = Some(average);
+        }
+
+        let state = self.state()?;
+        if state.charging || state.percent > self.config.low_percent + LOW_HYSTERESIS {
+            self.warned = false;
+        }
+        if state.charging {
+            self.emptied = false;
+        }
+        // Wait for a full window, so a single bad sample does not power down.
+        if !self.samples.is_full() || state.charging {
+            return None;
+        }
+        if state.percent <= self.config.shutdown_percent {
+            let first = !self.emptied;
+            self.emptied = true;
+            return first.then_some(BatteryEvent::Empty);
+        }
+        if state.low && !self.warned {
+            self.warned = true;
+            return Some(BatteryEvent::Low(state.percent));
+        }
+        None
+    }
+    /// Battery task body, samples the voltage and passes warnings to `on_event`.
+    pub async fn run(
+        &mut self,
+        sensor: &mut impl VoltageSensor,
+        mut on_event: impl FnMut(BatteryEvent),
+    ) -> ! {
+        loop {
+            let event = self.update(sensor.read_mv());
+            let state = self.state();
+            let previous = critical_section::with(|cs| STATE.borrow(cs).replace(state));
+            // The voltage changes with every sample, only log when the estimate changes.
+            let estimate = |state: Option<BatteryState>| state.map(|s| (s.percent, s.charging));
+            if estimate(previous) != estimate(state)
+                && let Some(state) = state
+            {
+                info!(
+                    "Battery: {} mV, {}%{}",
+                    state.millivolts,
+                    state.percent,
+                    if state.charging { ", charging" } else { "" }
+                );
+            }
+            if let Some(event) = event {
+                on_event(event);
+            }
+            Timer::after(SAMPLE_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_time::{advance, run_task};
+    use core::cell::RefCell;
+
+    // Reads a fixed voltage, like an ADC without noise.
+    struct MockSensor {
+        mv: u16,
+    }
+
+    impl VoltageSensor for MockSensor {
+        fn read_mv(&mut self) -> u16 {
+            self.mv
+        }
+    }
+
+    fn feed(monitor: &mut BatteryMonitor, mv: u16, samples: usize) -> Vec<BatteryEvent> {
+        let mut sensor = MockSensor { mv };
+        (0..samples)
+            .filter_map(|_| monitor.update(sensor.read_mv()))
+            .collect()
+    }
+
+    #[test]
+    fn curve_end_points() {
+        assert_eq!(estimate_percent(&LIPO_CURVE, 4300), 100);
+        assert_eq!(estimate_percent(&LIPO_CURVE, 4200), 100);
+        assert_eq!(estimate_percent(&LIPO_CURVE, 3300), 0);
+        assert_eq!(estimate_percent(&LIPO_CURVE, 2900), 0);
+        assert_eq!(estimate_percent(&[], 3700), 0);
+    }
+
+    #[test]
+    fn curve_is_interpolated() {
+        assert_eq!(estimate_percent(&LIPO_CURVE, 3900), 64);
+        assert_eq!(estimate_percent(&LIPO_CURVE, 4150), 95);
+        assert_eq!(estimate_percent(&LIPO_CURVE, 3725), 32);
+        assert_eq!(estimate_percent(&LIPO_CURVE, 3400), 1);
+    }
+
+    #[test]
+    fn unsorted_curve_does_not_overflow() {
+        let curves: [&[(u16, u8)]; 3] = [
+            &[(3300, 0), (4200, 100)],
+            &[(4200, 10), (4000, 90), (3300, 0)],
+            &[(4000, 50), (4000, 60), (3000, 0)],
+        ];
+        for curve in curves {
+            for mv in (2800..4400).step_by(10) {
+                assert!(estimate_percent(curve, mv) <= 100);
+            }
+        }
+    }
+
+    #[test]
+    fn low_warning_has_hysteresis() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(feed(&mut monitor, 3640, 16), [BatteryEvent::Low(13)]);
+        assert_eq!(feed(&mut monitor, 3640, 16), []);
+        // Still within the hysteresis.
+        assert_eq!(feed(&mut monitor, 3660, 16), []);
+        assert_eq!(feed(&mut monitor, 3640, 16), []);
+
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(feed(&mut monitor, 3640, 16), [BatteryEvent::Low(13)]);
+        assert_eq!(feed(&mut monitor, 3720, 16), []);
+        assert_eq!(feed(&mut monitor, 3640, 100), [BatteryEvent::Low(15)]);
+    }
+
+    #[test]
+    fn empty_is_sent_once() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        // Not before the window is full.
+        assert_eq!(feed(&mut monitor, 3400, 15), []);
+        assert_eq!(feed(&mut monitor, 3400, 50), [BatteryEvent::Empty]);
+    }
+
+    #[test]
+    fn charging_is_detected() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        feed(&mut monitor, 3800, 30);
+        assert!(!monitor.state().unwrap().charging);
+        feed(&mut monitor, 3850, 30);
+        assert!(monitor.state().unwrap().charging);
+        assert!(!monitor.state().unwrap().low);
+
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        feed(&mut monitor, 4300, 16);
+        assert!(monitor.state().unwrap().charging);
+        // Unplugged, the average is below the limit after 5 samples and stops rising.
+        feed(&mut monitor, 4100, 5 + TREND_SAMPLES as usize);
+        assert!(!monitor.state().unwrap().charging);
+    }
+
+    #[test]
+    fn no_battery() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(monitor.state(), None);
+        assert_eq!(feed(&mut monitor, 100, 20), []);
+        assert_eq!(monitor.state(), None);
+    }
+
+    #[test]
+    fn task_samples_at_the_interval() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        let mut sensor = MockSensor { mv: 3640 };
+        let events = RefCell::new(Vec::new());
+        let task = monitor.run(&mut sensor, |event| events.borrow_mut().push(event));
+        run_task(task, async {
+            assert_eq!(battery_state().map(|s| s.millivolts), Some(3640));
+            // The warning needs a full window of samples.
+            for _ in 1..WINDOW {
+                assert_eq!(*events.borrow(), []);
+                advance(SAMPLE_INTERVAL.as_millis()).await;
+            }
+            assert_eq!(*events.borrow(), [BatteryEvent::Low(13)]);
+        });
+    }
+}
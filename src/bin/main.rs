@@ -7,44 +7,231 @@
 )]
 
 use esp_hal::clock::CpuClock;
-use esp_hal::gpio::OutputConfig;
+use esp_hal::delay::Delay;
+use esp_hal::gpio::{Io, OutputConfig};
+use esp_hal::handler;
 use esp_hal::ledc::timer::*;
 use esp_hal::ledc::{Ledc, LowSpeed};
-use esp_hal::main;
+use esp_hal::rtc_cntl::Rtc;
 use esp_hal::time::{Instant, Rate};
-use pocket_computer::display::{DisplayDriver, DisplayPins};
-use pocket_computer::power::{PowerManager, PowerMode};
+use esp_hal::timer::timg::TimerGroup;
+use esp_hal::usb_serial_jtag::UsbSerialJtag;
+use pocket_computer::display::{DisplayDriver, DisplayPins, init_unmanaged};
+use pocket_computer::power::{
+    self, PowerMode, light_sleep_duration, power_mode, report_activity, wake_locks,
+};
+use pocket_computer::scheduler::FrameScheduler;
+use pocket_computer::service::{self, SystemService};
 
 use core::cell::RefCell;
-use log::info;
-use mem_fs::MemFs;
-use pocket_computer::apps::AppState;
-use pocket_computer::apps::home::HomeApp;
-use pocket_computer::input::{ButtonEvent, ButtonManager};
+use embassy_executor::Spawner;
+use log::{info, warn};
+use pocket_computer::adc::BatteryAdc;
+use pocket_computer::apps::AppID;
+use pocket_computer::apps::manager::AppManager;
+use pocket_computer::battery::{BatteryConfig, BatteryEvent, BatteryMonitor, battery_state};
+use pocket_computer::clock;
+use pocket_computer::console::{COMMANDS, ConsoleCmd, SerialConsole};
+use pocket_computer::cpu;
+use pocket_computer::crash;
+use pocket_computer::events::{InputEvent, TimedEvent, has_pending_events, pop_event, run_input};
+use pocket_computer::flash::FlashPartition;
+use pocket_computer::input::{ButtonEvent, ButtonManager, NavEvent};
+use pocket_computer::journal::JournalFs;
+use pocket_computer::keyboard::{KeyEvent, Keyboard};
+use pocket_computer::keys::{
+    HardwareKeyPins, HardwareKeys, install_hardware_keys, on_key_interrupt, poll_hardware_keys,
+};
 use pocket_computer::log::init_log;
-use pocket_computer::system::{SettingsView, SystemCmd, SystemSettings};
-use pocket_computer::touch::{TouchCalibration, TouchDriver, TouchPins, TouchPoller};
+use pocket_computer::storage::Storage;
+use pocket_computer::system::{
+    HwKeyAction, SYSTEM_NAMESPACE, SettingsView, SystemCmd, SystemResult, SystemSettings,
+};
+use pocket_computer::timers::TimerService;
+use pocket_computer::toast::TOAST_LEN;
+use pocket_computer::touch::{
+    TouchCalibration, TouchDriver, TouchPins, TouchPoller, install_touch_irq, on_touch_interrupt,
+    poll_touch,
+};
+use pocket_computer::watchdog::{AppCall, AppWatchdog, FEED_INTERVAL, Overrun, check_reset};
+use static_cell::StaticCell;
 
-use pocket_computer::apps::app::{App, AppCmd, Context, InputEvents};
+use pocket_computer::apps::app::{App, AppCmd, AppResponse, Context, InputEvents};
 use pocket_computer::graphics::*;
 
+// How long the panic screen is shown before restarting.
+const PANIC_RESTART_DELAY_MS: u32 = 10_000;
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     log::error!("ERROR: {}", info.message());
-    loop {}
+    let report = crash::record_panic(info);
+    log::error!("At {} in {}", report.location, report.app);
+
+    // The display driver belongs to the main task, so set up the display again.
+    // SAFETY: The main task does not run anymore.
+    let pins = unsafe { DisplayPins::steal() };
+    if let Some(mut display) = init_unmanaged(pins, OutputConfig::default()) {
+        let _ = crash::draw_panic_screen(&mut display, &report);
+    }
+
+    Delay::new().delay_millis(PANIC_RESTART_DELAY_MS);
+    esp_hal::system::software_reset()
+}
+
+// All GPIO interrupts share one handler.
+#[handler]
+fn gpio_irq_handler() {
+    on_touch_interrupt();
+    on_key_interrupt();
+}
+
+#[embassy_executor::task]
+async fn input_task() -> ! {
+    run_input(|| {
+        let touching = poll_touch();
+        let keys_held = poll_hardware_keys();
+        touching || keys_held
+    })
+    .await
+}
+
+#[embassy_executor::task]
+async fn console_task(mut console: SerialConsole<'static>) -> ! {
+    console.run().await
+}
+
+#[embassy_executor::task]
+async fn battery_task(mut adc: BatteryAdc<'static>) -> ! {
+    BatteryMonitor::new(BatteryConfig::default())
+        .run(&mut adc, |event| match event {
+            BatteryEvent::Low(percent) => {
+                warn!("Battery low: {}%", percent);
+                let text = heapless::format!(TOAST_LEN; "Battery low: {}%", percent);
+                service::send(SystemCmd::ShowToast(text.unwrap_or_default()));
+            }
+            BatteryEvent::Empty => {
+                warn!("Battery empty, powering off");
+                service::send(SystemCmd::PowerOff);
+            }
+        })
+        .await
+}
+
+#[embassy_executor::task]
+async fn power_task(settings: &'static RefCell<SystemSettings>) -> ! {
+    power::run(settings).await
+}
+
+// Close an app that took too long and tell the user which one it was.
+// The screen is always redrawn afterwards.
+fn kill_app(
+    overrun: Overrun,
+    active_app: &mut AppManager,
+    ctx: &mut Context,
+    keyboard: &mut Keyboard,
+    system: &mut SystemService,
+    system_result: &mut Option<(AppID, SystemResult)>,
+) {
+    keyboard.close(ctx.buttons, ctx.grid);
+    let response = active_app.kill(ctx);
+    let text = heapless::format!(
+        TOAST_LEN;
+        "{} stopped responding ({})",
+        overrun.app,
+        overrun.call.name()
+    )
+    .unwrap_or_default();
+    system.show_toast(&text);
+    apply_response(response, active_app, ctx, keyboard, system, system_result);
+}
+
+// Opening an app returns the response of its `init`, which can open another app in turn.
+const MAX_CHAINED_RESPONSES: usize = 4;
+
+// Carry out the response of an app and the responses of the apps it opened or closed.
+// Returns true when the screen has to be redrawn.
+fn apply_response(
+    mut response: AppResponse,
+    active_app: &mut AppManager,
+    ctx: &mut Context,
+    keyboard: &mut Keyboard,
+    system: &mut SystemService,
+    system_result: &mut Option<(AppID, SystemResult)>,
+) -> bool {
+    let mut dirty = false;
+    for _ in 0..MAX_CHAINED_RESPONSES {
+        // The result of the system command is only passed to the app that sent it.
+        let sender = active_app.active_id();
+        let next = match response.app {
+            AppCmd::None => None,
+            AppCmd::Dirty => {
+                dirty = true;
+                None
+            }
+            AppCmd::SwitchApp(app) => {
+                keyboard.close(ctx.buttons, ctx.grid);
+                Some(active_app.switch(app, ctx))
+            }
+            AppCmd::PushApp(app) => {
+                keyboard.close(ctx.buttons, ctx.grid);
+                Some(active_app.push(app, ctx))
+            }
+            AppCmd::PushScreen(screen) => {
+                dirty = true;
+                Some(active_app.push_screen(screen, ctx))
+            }
+            AppCmd::Back => {
+                keyboard.close(ctx.buttons, ctx.grid);
+                dirty = true;
+                Some(active_app.pop(ctx))
+            }
+            AppCmd::Start(intent) => {
+                keyboard.close(ctx.buttons, ctx.grid);
+                dirty = true;
+                Some(active_app.start(intent, ctx))
+            }
+            AppCmd::Finish(data) => {
+                keyboard.close(ctx.buttons, ctx.grid);
+                dirty = true;
+                Some(active_app.finish(data, ctx))
+            }
+        };
+        if let Some(cmd) = response.system {
+            *system_result = Some((sender, system.handle(cmd, ctx, keyboard, active_app)));
+            dirty = true;
+        }
+        // An app resumed by the command is superseded by the app opened above.
+        let resumed = system.take_app_response();
+        match next.or(resumed) {
+            Some(next) => response = next,
+            None => return dirty,
+        }
+    }
+    warn!("Apps kept opening each other, ignoring the last response");
+    dirty
 }
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
-#[main]
-fn main() -> ! {
+// The main task runs the UI: apps, buttons and rendering.
+#[esp_rtos::main]
+async fn main(spawner: Spawner) -> ! {
     init_log(log::LevelFilter::Info).expect("Failed to initialize logger...");
+    // The power modes lower the clock from here, `cpu` relies on the PLL of the maximum.
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
     let output_config = OutputConfig::default();
 
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    esp_rtos::start(timg0.timer0);
+
+    // Report a hang of the previous run before the watchdog is armed again.
+    check_reset();
+    let wdt = TimerGroup::new(peripherals.TIMG1).wdt;
+
     let mut ledc = Ledc::new(peripherals.LEDC);
     ledc.set_global_slow_clock(esp_hal::ledc::LSGlobalClkSource::APBClk);
 
@@ -57,7 +244,7 @@ fn main() -> ! {
         })
         .expect("Failed to create Timer..");
 
-    let mut display_driver = DisplayDriver::init(
+    let display_driver = DisplayDriver::init(
         DisplayPins {
             d0: peripherals.GPIO48,
             d1: peripherals.GPIO47,
@@ -80,7 +267,9 @@ fn main() -> ! {
     let mut screen_buffer = [Cell::default(); ((SCREEN_W / CELL_W) * (SCREEN_H / CELL_H)) as usize];
     let mut screen_grid = ScreenGrid::new(SCREEN_W / CELL_W, SCREEN_H / CELL_H, &mut screen_buffer);
 
-    let mut touch_driver = TouchDriver::new(TouchPins {
+    let mut io = Io::new(peripherals.IO_MUX);
+    io.set_interrupt_handler(gpio_irq_handler);
+    let touch_driver = TouchDriver::new(TouchPins {
         spi: peripherals.SPI2,
         sclk: peripherals.GPIO1,
         miso: peripherals.GPIO4,
@@ -89,104 +278,325 @@ fn main() -> ! {
         irq: peripherals.GPIO9,
     });
 
-    // TODO: Load or calibrate touch here.
-    let touch_calibration = TouchCalibration::default();
-    let mut touch_poller = TouchPoller::new(touch_calibration, &mut touch_driver);
+    // The saved calibration is applied with the other settings.
+    install_touch_irq(TouchPoller::new(TouchCalibration::default(), touch_driver));
+
+    install_hardware_keys(HardwareKeys::new(HardwareKeyPins {
+        boot: peripherals.GPIO0,
+        user: peripherals.GPIO21,
+    }));
+
+    // Only RX is used, logging keeps writing to the same port through esp-println.
+    let (serial_rx, _) = UsbSerialJtag::new(peripherals.USB_DEVICE).split();
+    let console = SerialConsole::new(serial_rx);
 
     let mut button_manager = ButtonManager::new();
     button_manager.register_default_buttons();
+    let mut keyboard = Keyboard::new();
 
     // Timers
     let mut last_render_time = 0;
+    let mut shown_status = StatusInfo::default();
 
-    let mut fs = MemFs::new();
-    let settings = RefCell::new(SystemSettings::default());
-    let mut power_manager = PowerManager::new();
+    static FLASH: StaticCell<FlashPartition> = StaticCell::new();
+    let mut fs = JournalFs::mount(FLASH.init(FlashPartition::new(peripherals.FLASH)));
+    static SETTINGS: StaticCell<RefCell<SystemSettings>> = StaticCell::new();
+    let settings = SETTINGS.init(RefCell::new(SystemSettings::load(&Storage::new(
+        &mut fs,
+        SYSTEM_NAMESPACE,
+    ))));
+    clock::init(Rtc::new(peripherals.LPWR), settings.borrow().keep_time);
+    let mut timers = TimerService::new();
+    let mut scheduler = FrameScheduler::new();
+    let mut app_watchdog = AppWatchdog::new();
+    let mut system = SystemService::new(display_driver, wdt, settings);
+    // Result of the last system command and the app that sent it, passed on with its next update.
+    let mut system_result = None;
 
-    let mut active_app = AppState::Home(HomeApp::default());
+    let mut active_app = AppManager::new(AppID::HomeApp);
     let mut ctx = Context {
         grid: &mut screen_grid,
         buttons: &mut button_manager,
-        settings: SettingsView::new(&settings),
-        fs: &mut fs,
+        settings: SettingsView::new(settings),
+        storage: Storage::new(&mut fs, AppID::HomeApp.info().name),
+        timers: &mut timers,
     };
 
+    spawner.must_spawn(input_task());
+    spawner.must_spawn(console_task(console));
+    spawner.must_spawn(power_task(settings));
+    spawner.must_spawn(battery_task(BatteryAdc::new(
+        peripherals.ADC1,
+        peripherals.GPIO5,
+    )));
+
+    system.apply_settings(ctx.grid);
     active_app.init(&mut ctx);
-    display_driver.set_backlight(settings.borrow().user_brightness);
     loop {
-        let update_time = Instant::now();
-        let touch_event = touch_poller.poll();
-        if touch_event.is_some() {
-            power_manager.register_activity();
+        scheduler.begin_frame();
+        system.feed_watchdog();
+        // A key press that turns the screen back on should not trigger its action.
+        let was_asleep = matches!(power_mode(), PowerMode::Sleep | PowerMode::Off);
+
+        system.save_settings(&mut ctx, Instant::now());
+        let mut dirty = system.expire_toast(Instant::now());
+        dirty |= system.process_queue(&mut ctx, &mut keyboard, &mut active_app);
+        if let Some(response) = system.take_app_response() {
+            dirty |= apply_response(
+                response,
+                &mut active_app,
+                &mut ctx,
+                &mut keyboard,
+                &mut system,
+                &mut system_result,
+            );
+        }
+        while let Ok(cmd) = COMMANDS.try_receive() {
+            match cmd {
+                ConsoleCmd::Launch(app) => {
+                    report_activity();
+                    dirty |= apply_response(
+                        AppResponse::push(app),
+                        &mut active_app,
+                        &mut ctx,
+                        &mut keyboard,
+                        &mut system,
+                        &mut system_result,
+                    );
+                }
+                ConsoleCmd::Time(None) => match clock::local_now(settings.borrow().time_zone) {
+                    Some(time) => info!("Time: {} {}", time.weekday_name(), time),
+                    None => info!("Time: not set"),
+                },
+                ConsoleCmd::Time(Some(time)) => {
+                    let cmd = SystemCmd::SetTime(time);
+                    if let Err(err) = system.handle(cmd, &mut ctx, &mut keyboard, &mut active_app) {
+                        warn!("Setting the time failed: {:?}", err);
+                    }
+                    dirty = true;
+                }
+                _ => {}
+            }
         }
-        let button_event = if let Some(touch_event) = &touch_event {
-            ctx.buttons.update(touch_event)
-        } else {
-            None
+        // Redraw the status bar when the minute or the battery changes.
+        let status = || {
+            StatusInfo::new(
+                clock::local_now(settings.borrow().time_zone),
+                battery_state(),
+            )
         };
+        dirty |= status() != shown_status;
 
-        let mut dirty = false;
-        // Check navigation buttons
-        if let Some(ButtonEvent::Up(id)) = button_event {
-            if id == "BACK" {
-                active_app = active_app.switch(pocket_computer::apps::app::AppID::HomeApp);
-                dirty = active_app.init(&mut ctx).app == AppCmd::Dirty;
+        let mut handled_events = 0;
+        // Drain every queued event, so input that arrived between frames reaches the app.
+        // Expired timers are delivered after the input, one update per timer.
+        // The app is updated at least once per frame, even without input.
+        loop {
+            let mut nav = None;
+            // A result for an app that is not active anymore is dropped.
+            if matches!(system_result, Some((sender, _)) if sender != active_app.active_id()) {
+                system_result = None;
             }
-        };
+            // The result of a system command gets an update of its own, so apps that only
+            // look at the result do not drop the input that would come with it.
+            let next_event = match system_result {
+                Some(_) => None,
+                None => pop_event(),
+            };
+            let mut input = match next_event {
+                Some(TimedEvent { time, event }) => {
+                    report_activity();
+                    let mut input = InputEvents::new(time);
+                    match event {
+                        InputEvent::Touch(touch) => input.touch = Some(touch),
+                        InputEvent::HwKey(key) => input.hw_key = Some(key),
+                        // Keys from the serial console also edit the on-screen keyboard text.
+                        InputEvent::Key(key) if keyboard.is_open() => {
+                            input.key = keyboard.type_key(key, ctx.buttons, ctx.grid);
+                            dirty = true;
+                        }
+                        // Enter presses the focused button.
+                        InputEvent::Key(KeyEvent::Enter) if ctx.buttons.has_focus() => {
+                            nav = Some(NavEvent::Activate)
+                        }
+                        InputEvent::Key(key) => input.key = Some(key),
+                        InputEvent::Nav(event) => nav = Some(event),
+                    }
+                    input
+                }
+                None if system_result.is_some() => {
+                    let mut input = InputEvents::new(Instant::now());
+                    input.system = system_result.take().map(|(_, result)| result);
+                    input
+                }
+                None => {
+                    let now = Instant::now();
+                    match ctx.timers.pop_expired(now) {
+                        Some(id) => {
+                            let mut input = InputEvents::new(now);
+                            input.timer = Some(id);
+                            input
+                        }
+                        // Intent arguments and results are delivered right after switching apps.
+                        None if handled_events == 0 || active_app.has_pending() => {
+                            InputEvents::new(now)
+                        }
+                        None => break,
+                    }
+                }
+            };
+            handled_events += 1;
 
-        let response = active_app.update(
-            InputEvents {
-                touch: touch_event,
-                button: button_event,
-            },
-            &mut ctx,
-        );
-
-        dirty |= match response.app {
-            AppCmd::None => false,
-            AppCmd::Dirty => true,
-            AppCmd::SwitchApp(app) => {
-                active_app = active_app.switch(app);
-                active_app.init(&mut ctx).app == AppCmd::Dirty
+            let mut go_home = false;
+            let mut go_back = false;
+
+            // Hardware keys mapped to a system action are not passed on to the app.
+            if let Some(key) = input.hw_key {
+                let action = settings.borrow().key_action(key);
+                if was_asleep {
+                    input.hw_key = None;
+                } else if action != Some(HwKeyAction::App) {
+                    match action {
+                        Some(HwKeyAction::FocusNext) => nav = Some(NavEvent::Next),
+                        Some(HwKeyAction::FocusPrev) => nav = Some(NavEvent::Prev),
+                        Some(HwKeyAction::Activate) => nav = Some(NavEvent::Activate),
+                        Some(HwKeyAction::Back) => go_back = true,
+                        Some(_) => go_home = true,
+                        None => {}
+                    }
+                    input.hw_key = None;
+                }
             }
-        };
 
-        if let Some(cmd) = response.system {
-            match cmd {
-                SystemCmd::SetBrightness(val) => {
-                    let mut s = settings.borrow_mut();
-                    s.user_brightness = val;
-                    display_driver.set_backlight(s.user_brightness);
-                    lstimer0.update_hw();
+            if let Some(touch_event) = &input.touch {
+                input.button = ctx.buttons.update(touch_event);
+            } else if let Some(nav) = nav {
+                input.button = ctx.buttons.navigate(nav);
+            }
+
+            // Keys of the on-screen keyboard are handled by the system, the app only sees the KeyEvent.
+            if keyboard.is_open() {
+                match input.button {
+                    Some(ButtonEvent::Up(id)) if keyboard.owns(id) => {
+                        input.key = keyboard.handle(id, ctx.buttons, ctx.grid);
+                        input.button = None;
+                        dirty = true;
+                    }
+                    Some(ButtonEvent::Down(id)) if keyboard.owns(id) => input.button = None,
+                    _ => {}
                 }
-                _ => {}
             }
+
+            // Check navigation buttons
+            go_back |= input.button == Some(ButtonEvent::Up("BACK"));
+
+            let nav_response = if go_home {
+                keyboard.close(ctx.buttons, ctx.grid);
+                Some(active_app.home(&mut ctx))
+            } else if go_back {
+                keyboard.close(ctx.buttons, ctx.grid);
+                // The title bar shows the breadcrumb, so always redraw.
+                dirty = true;
+                Some(active_app.back(&mut ctx))
+            } else {
+                None
+            };
+            if let Some(response) = nav_response {
+                dirty |= apply_response(
+                    response,
+                    &mut active_app,
+                    &mut ctx,
+                    &mut keyboard,
+                    &mut system,
+                    &mut system_result,
+                );
+            }
+
+            app_watchdog.begin(active_app.get_name(), AppCall::Update);
+            let response = active_app.update(input, &mut ctx);
+            if let Some(overrun) = app_watchdog.end() {
+                kill_app(
+                    overrun,
+                    &mut active_app,
+                    &mut ctx,
+                    &mut keyboard,
+                    &mut system,
+                    &mut system_result,
+                );
+                dirty = true;
+                continue;
+            }
+
+            dirty |= apply_response(
+                response,
+                &mut active_app,
+                &mut ctx,
+                &mut keyboard,
+                &mut system,
+                &mut system_result,
+            );
         }
 
+        system.commit_storage(&mut ctx);
         let dirty = dirty || ctx.buttons.is_dirty();
 
-        if dirty && power_manager.get_power_mode() != PowerMode::Sleep {
+        let asleep = matches!(power_mode(), PowerMode::Sleep | PowerMode::Off);
+        if dirty && !asleep {
+            let _boost = cpu::boost();
             let render_time = Instant::now();
+            app_watchdog.begin(active_app.get_name(), AppCall::Render);
             active_app.render(&mut ctx);
-            draw_status_bars(&mut ctx.grid, active_app.get_name(), last_render_time);
+            if let Some(overrun) = app_watchdog.end() {
+                kill_app(
+                    overrun,
+                    &mut active_app,
+                    &mut ctx,
+                    &mut keyboard,
+                    &mut system,
+                    &mut system_result,
+                );
+                active_app.render(&mut ctx);
+            }
+            keyboard.render(ctx.grid);
+            shown_status = status();
+            draw_status_bars(
+                &mut ctx.grid,
+                &active_app.breadcrumb(),
+                last_render_time,
+                &shown_status,
+            );
+            system.toast().render(ctx.grid);
             ctx.buttons.draw_buttons(ctx.grid);
-            render_grid(display_driver.display_mut(), &mut ctx.grid).unwrap();
+            render_grid(system.display().display_mut(), &mut ctx.grid).unwrap();
 
             last_render_time = render_time.elapsed().as_millis();
             info!("Rendering took: {} ms", last_render_time);
         }
 
-        // TODO: Handle system cmd's in a uniform way
-        if let Some(cmd) = power_manager.update(&settings) {
-            if let SystemCmd::SetBrightness(val) = cmd {
-                display_driver.set_backlight(val);
-            }
+        if let Some(deadline) = ctx.timers.next_deadline() {
+            scheduler.wake_at(deadline);
         }
-
-        let update_time = update_time.elapsed().as_millis();
-        if update_time > 0 {
-            info!("Total update took: {}ms", update_time);
+        if let Some(deadline) = system.toast().deadline() {
+            scheduler.wake_at(deadline);
+        }
+        if let Some(deadline) = clock::next_minute() {
+            scheduler.wake_at(deadline);
+        }
+        if let Some(deadline) = system.save_deadline() {
+            scheduler.wake_at(deadline);
+        }
+        // With the screen off the CPU waits for the next deadline in light sleep,
+        // unless an app holds a wake lock for it.
+        if power_mode() == PowerMode::Sleep && wake_locks().cpu == 0 {
+            // Let the other tasks finish their work first.
+            embassy_futures::yield_now().await;
+            if !has_pending_events() {
+                system.light_sleep(light_sleep_duration(scheduler.next_wake()));
+                power::recheck();
+            }
         }
-        power_manager.await_frame();
+        // Keep feeding the hardware watchdog while idle.
+        scheduler.wake_at(Instant::now() + FEED_INTERVAL);
+        scheduler.await_next_frame().await;
     }
 }
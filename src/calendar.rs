@@ -0,0 +1,236 @@
+use core::fmt;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+// Range of the years that can be set.
+const MIN_YEAR: i32 = 2000;
+const MAX_YEAR: i32 = 2099;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// When daylight saving time is in effect.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DstRule {
+    None,
+    // Last Sunday of March to the last Sunday of October, at 01:00 UTC.
+    Europe,
+    // Second Sunday of March to the first Sunday of November, at 02:00 local time.
+    NorthAmerica,
+}
+
+impl DstRule {
+    // In the order of their stored value.
+    pub const ALL: [DstRule; 3] = [DstRule::None, DstRule::Europe, DstRule::NorthAmerica];
+
+    pub fn next(&self) -> Self {
+        match self {
+            DstRule::None => DstRule::Europe,
+            DstRule::Europe => DstRule::NorthAmerica,
+            DstRule::NorthAmerica => DstRule::None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            DstRule::None => "Off",
+            DstRule::Europe => "Europe",
+            DstRule::NorthAmerica => "North America",
+        }
+    }
+}
+
+/// Standard offset from UTC and the daylight saving rule of the local time.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TimeZone {
+    pub offset_min: i16,
+    pub dst: DstRule,
+}
+
+impl TimeZone {
+    pub const UTC: TimeZone = TimeZone {
+        offset_min: 0,
+        dst: DstRule::None,
+    };
+    pub const MIN_OFFSET: i16 = -12 * 60;
+    pub const MAX_OFFSET: i16 = 14 * 60;
+
+    /// Offsets are whole quarter hours, like every zone in use.
+    pub fn is_valid(&self) -> bool {
+        (Self::MIN_OFFSET..=Self::MAX_OFFSET).contains(&self.offset_min)
+            && self.offset_min % 15 == 0
+    }
+    /// Whether daylight saving time is in effect at `utc`, in seconds since 1970.
+    pub fn is_dst(&self, utc: i64) -> bool {
+        let year = DateTime::from_timestamp(utc).year;
+        let standard = self.offset_min as i64 * 60;
+        let (start, end) = match self.dst {
+            DstRule::None => return false,
+            DstRule::Europe => (
+                last_sunday(year, 3) * SECS_PER_DAY + 3600,
+                last_sunday(year, 10) * SECS_PER_DAY + 3600,
+            ),
+            // Ends at 02:00 daylight time, which is 01:00 standard time.
+            DstRule::NorthAmerica => (
+                nth_sunday(year, 3, 2) * SECS_PER_DAY + 2 * 3600 - standard,
+                nth_sunday(year, 11, 1) * SECS_PER_DAY + 3600 - standard,
+            ),
+        };
+        (start..end).contains(&utc)
+    }
+    /// Seconds from UTC to the local time at `utc`.
+    pub fn offset_at(&self, utc: i64) -> i64 {
+        let dst = if self.is_dst(utc) { 3600 } else { 0 };
+        self.offset_min as i64 * 60 + dst
+    }
+    pub fn to_local(&self, utc: i64) -> DateTime {
+        DateTime::from_timestamp(utc + self.offset_at(utc))
+    }
+    /// UTC of a local time. Times skipped or repeated by the DST change resolve to standard time.
+    pub fn to_utc(&self, local: &DateTime) -> i64 {
+        let utc = local.timestamp() - self.offset_min as i64 * 60;
+        if self.is_dst(utc - 3600) {
+            utc - 3600
+        } else {
+            utc
+        }
+    }
+}
+
+/// A calendar date and time of day, without a time zone.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// `None` when a field is out of range.
+    pub fn new(year: i32, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let time = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        time.is_valid().then_some(time)
+    }
+    pub fn is_valid(&self) -> bool {
+        (MIN_YEAR..=MAX_YEAR).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+    /// `secs` since 1970.
+    pub fn from_timestamp(secs: i64) -> Self {
+        let days = secs.div_euclid(SECS_PER_DAY);
+        let time = secs.rem_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+    /// Seconds since 1970.
+    pub fn timestamp(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+    pub fn weekday_name(&self) -> &'static str {
+        WEEKDAYS[weekday(days_from_civil(self.year, self.month, self.day))]
+    }
+    /// Parse a date as `YYYY-MM-DD` and a time as `HH:MM` or `HH:MM:SS`.
+    pub fn parse(date: &str, time: &str) -> Option<Self> {
+        let mut date = date.split('-');
+        let year = date.next()?.parse().ok()?;
+        let month = date.next()?.parse().ok()?;
+        let day = date.next()?.parse().ok()?;
+        let mut time = time.split(':');
+        let hour = time.next()?.parse().ok()?;
+        let minute = time.next()?.parse().ok()?;
+        let second = time.next().map_or(Some(0), |s| s.parse().ok())?;
+        if date.next().is_some() || time.next().is_some() {
+            return None;
+        }
+        Self::new(year, month, day, hour, minute, second)
+    }
+    /// The same day and time `months` later, the day is clamped to the length of the month.
+    pub fn add_months(&self, months: i32) -> Option<Self> {
+        let index = self.year * 12 + self.month as i32 - 1 + months;
+        let (year, month) = (index.div_euclid(12), index.rem_euclid(12) as u8 + 1);
+        let day = self.day.min(days_in_month(year, month));
+        Self::new(year, month, day, self.hour, self.minute, self.second)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970, from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let year = year as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year as i32, month, day)
+}
+
+// 0 is Sunday, 1970-01-01 was a Thursday.
+fn weekday(days: i64) -> usize {
+    (days + 4).rem_euclid(7) as usize
+}
+
+// Day of the `n`th Sunday of the month.
+fn nth_sunday(year: i32, month: u8, n: i64) -> i64 {
+    let first = days_from_civil(year, month, 1);
+    first + (7 - weekday(first) as i64) % 7 + (n - 1) * 7
+}
+
+fn last_sunday(year: i32, month: u8) -> i64 {
+    let last = days_from_civil(year, month, days_in_month(year, month));
+    last - weekday(last) as i64
+}
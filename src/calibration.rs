@@ -0,0 +1,75 @@
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TouchCalibration {
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+impl Default for TouchCalibration {
+    fn default() -> Self {
+        Self {
+            min_x: 314,
+            min_y: 297,
+            max_x: 3707,
+            max_y: 3656,
+        }
+    }
+}
+
+/// How light a touch may be to still count, see `TouchSensitivity::min_pressure`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TouchSensitivity {
+    Low,
+    Medium,
+    High,
+}
+
+impl TouchSensitivity {
+    // In the order of their stored value.
+    pub const ALL: [TouchSensitivity; 3] = [
+        TouchSensitivity::Low,
+        TouchSensitivity::Medium,
+        TouchSensitivity::High,
+    ];
+
+    pub fn next(self) -> Self {
+        match self {
+            TouchSensitivity::Low => TouchSensitivity::Medium,
+            TouchSensitivity::Medium => TouchSensitivity::High,
+            TouchSensitivity::High => TouchSensitivity::Low,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            TouchSensitivity::Low => "Low",
+            TouchSensitivity::Medium => "Medium",
+            TouchSensitivity::High => "High",
+        }
+    }
+    // Lowest Z1 reading of a touch, lighter touches are ignored.
+    pub(crate) fn min_pressure(self) -> u16 {
+        match self {
+            TouchSensitivity::Low => 400,
+            TouchSensitivity::Medium => 200,
+            TouchSensitivity::High => 50,
+        }
+    }
+}
+
+fn map(raw: u16, min: u16, max: u16, out_max: u16) -> u16 {
+    if max <= min || raw < min {
+        return 0;
+    }
+
+    let num = (raw - min) as u32 * out_max as u32;
+    let den = (max - min) as u32;
+
+    (num / den) as u16
+}
+
+pub(crate) fn map_touch(raw_x: u16, raw_y: u16, calibration: &TouchCalibration) -> (u16, u16) {
+    let x = map(raw_x, calibration.min_x, calibration.max_x, 239);
+    let y = map(raw_y, calibration.min_y, calibration.max_y, 319);
+    (x, y)
+}
@@ -0,0 +1,122 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_hal::{
+    ram,
+    rtc_cntl::Rtc,
+    time::{Duration, Instant},
+};
+
+// Marks a set clock, RTC RAM holds random data after power on.
+const MAGIC: u32 = 0x434c_4f43;
+
+const MICROS_PER_MINUTE: u64 = 60_000_000;
+
+pub use crate::calendar::{DateTime, DstRule, TimeZone, days_in_month};
+
+// Offset from the RTC time to UTC, only plain integers so any bit pattern is valid.
+#[repr(C)]
+struct ClockRecord {
+    magic: u32,
+    checksum: u32,
+    offset_us: u64,
+}
+
+impl ClockRecord {
+    const EMPTY: Self = Self {
+        magic: 0,
+        checksum: 0,
+        offset_us: 0,
+    };
+
+    fn checksum(&self) -> u32 {
+        (self.offset_us as u32).rotate_left(7) ^ (self.offset_us >> 32) as u32 ^ MAGIC
+    }
+}
+
+// SAFETY: Only integers.
+unsafe impl esp_hal::Persistable for ClockRecord {}
+
+// The RTC timer keeps counting through sleep and software resets, so the offset stays valid.
+#[ram(unstable(rtc_fast, persistent))]
+static mut CLOCK: ClockRecord = ClockRecord::EMPTY;
+
+static RTC: Mutex<RefCell<Option<Rtc<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// Start the clock, the time set before a reset is forgotten unless `keep_time` is set.
+pub fn init(rtc: Rtc<'static>, keep_time: bool) {
+    critical_section::with(|cs| {
+        if !keep_time {
+            // SAFETY: Only accessed inside a critical section.
+            unsafe { (*core::ptr::addr_of_mut!(CLOCK)).magic = 0 };
+        }
+        RTC.borrow_ref_mut(cs).replace(rtc);
+    });
+}
+
+// Microseconds since 1970 in UTC.
+fn now_us() -> Option<u64> {
+    critical_section::with(|cs| {
+        // SAFETY: Only accessed inside a critical section.
+        let record = unsafe { &*core::ptr::addr_of!(CLOCK) };
+        if record.magic != MAGIC || record.checksum != record.checksum() {
+            return None;
+        }
+        let rtc_us = RTC.borrow_ref(cs).as_ref()?.time_since_boot().as_micros();
+        Some(rtc_us.wrapping_add(record.offset_us))
+    })
+}
+
+/// Seconds since 1970 in UTC, `None` until the clock is set.
+pub fn now_utc() -> Option<i64> {
+    now_us().map(|us| (us / 1_000_000) as i64)
+}
+
+pub fn local_now(zone: TimeZone) -> Option<DateTime> {
+    now_utc().map(|utc| zone.to_local(utc))
+}
+
+/// Set the clock to `utc` seconds since 1970.
+pub fn set_utc(utc: i64) {
+    critical_section::with(|cs| {
+        let Some(rtc_us) = RTC
+            .borrow_ref(cs)
+            .as_ref()
+            .map(|rtc| rtc.time_since_boot().as_micros())
+        else {
+            return;
+        };
+        let mut record = ClockRecord {
+            magic: MAGIC,
+            checksum: 0,
+            offset_us: (utc as u64 * 1_000_000).wrapping_sub(rtc_us),
+        };
+        record.checksum = record.checksum();
+        // SAFETY: Only accessed inside a critical section.
+        unsafe { core::ptr::addr_of_mut!(CLOCK).write(record) };
+    });
+}
+
+/// Milliseconds on the RTC timer, which keeps counting in light sleep unlike `Instant`.
+pub fn monotonic_ms() -> u64 {
+    critical_section::with(|cs| match RTC.borrow_ref(cs).as_ref() {
+        Some(rtc) => rtc.time_since_boot().as_millis(),
+        None => Instant::now().duration_since_epoch().as_millis(),
+    })
+}
+
+/// Run `f` with the RTC, outside of a critical section so it can put the CPU to sleep.
+///
+/// Returns `None` when the clock was not started.
+pub fn with_rtc<R>(f: impl FnOnce(&mut Rtc<'static>) -> R) -> Option<R> {
+    let mut rtc = critical_section::with(|cs| RTC.borrow_ref_mut(cs).take())?;
+    let result = f(&mut rtc);
+    critical_section::with(|cs| RTC.borrow_ref_mut(cs).replace(rtc));
+    Some(result)
+}
+
+/// When the minute shown by the clock changes, `None` while it is not set.
+pub fn next_minute() -> Option<Instant> {
+    let us = now_us()?;
+    Some(Instant::now() + Duration::from_micros(MICROS_PER_MINUTE - us % MICROS_PER_MINUTE))
+}
@@ -0,0 +1,281 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+use esp_hal::{Blocking, usb_serial_jtag::UsbSerialJtagRx};
+use log::{error, info};
+
+use crate::{
+    apps::app::AppID,
+    clock::DateTime,
+    events::{InputEvent, push_event, request_wake},
+    graphics::{SCREEN_H, SCREEN_W},
+    input::NavEvent,
+    keyboard::KeyEvent,
+    touch::TouchEvent,
+};
+
+pub const MAX_LINE: usize = 64;
+
+// Ctrl-C, leaves raw key mode.
+const EXIT_RAW: u8 = 0x03;
+
+const ESC: u8 = 0x1b;
+
+// The USB serial port is polled, it has no interrupt wired up.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Commands that are not input, like `launch`, for the UI task.
+pub static COMMANDS: Channel<CriticalSectionRawMutex, ConsoleCmd, 4> = Channel::new();
+
+pub const HELP: &str = "Commands: tap <x> <y> | swipe <x0> <y0> <x1> <y1> | key <char|enter|bksp|space> | text <str> | nav <next|prev|ok> | launch <app> | time [YYYY-MM-DD HH:MM[:SS]] | keys (raw keys, tab/arrows move focus, ctrl-c exits) | help";
+
+#[derive(PartialEq, Debug)]
+pub enum ConsoleCmd {
+    Tap { x: u16, y: u16 },
+    Swipe { from: (u16, u16), to: (u16, u16) },
+    Key(KeyEvent),
+    Nav(NavEvent),
+    Text(heapless::String<MAX_LINE>),
+    Launch(AppID),
+    // Show the time, or set it to a local time.
+    Time(Option<DateTime>),
+    RawKeys,
+    Help,
+}
+
+/// Parse a single command line.
+pub fn parse_command(line: &str) -> Result<ConsoleCmd, &'static str> {
+    let line = line.trim();
+    let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+    let mut words = args.split_whitespace();
+
+    match cmd {
+        "tap" => {
+            let (x, y) = parse_point(&mut words)?;
+            Ok(ConsoleCmd::Tap { x, y })
+        }
+        "swipe" => {
+            let from = parse_point(&mut words)?;
+            let to = parse_point(&mut words)?;
+            Ok(ConsoleCmd::Swipe { from, to })
+        }
+        "key" => parse_key(words.next().ok_or("key needs a key name")?).map(ConsoleCmd::Key),
+        "nav" => match words.next() {
+            Some("next") => Ok(ConsoleCmd::Nav(NavEvent::Next)),
+            Some("prev") => Ok(ConsoleCmd::Nav(NavEvent::Prev)),
+            Some("ok") => Ok(ConsoleCmd::Nav(NavEvent::Activate)),
+            _ => Err("nav needs next, prev or ok"),
+        },
+        "text" => {
+            let mut text = heapless::String::new();
+            text.push_str(args).map_err(|_| "text too long")?;
+            Ok(ConsoleCmd::Text(text))
+        }
+        "launch" => {
+            let name = words.next().ok_or("launch needs an app name")?;
+            AppID::from_name(name)
+                .map(ConsoleCmd::Launch)
+                .ok_or("unknown app")
+        }
+        "time" => match (words.next(), words.next()) {
+            (None, _) => Ok(ConsoleCmd::Time(None)),
+            (Some(date), Some(time)) => DateTime::parse(date, time)
+                .map(|t| ConsoleCmd::Time(Some(t)))
+                .ok_or("time needs YYYY-MM-DD HH:MM[:SS]"),
+            _ => Err("time needs a date and a time"),
+        },
+        "keys" => Ok(ConsoleCmd::RawKeys),
+        "help" => Ok(ConsoleCmd::Help),
+        _ => Err("unknown command, try help"),
+    }
+}
+
+fn parse_point<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<(u16, u16), &'static str> {
+    let x: u16 = words
+        .next()
+        .and_then(|w| w.parse().ok())
+        .ok_or("expected x coordinate")?;
+    let y: u16 = words
+        .next()
+        .and_then(|w| w.parse().ok())
+        .ok_or("expected y coordinate")?;
+    if x >= SCREEN_W || y >= SCREEN_H {
+        return Err("coordinate outside of screen");
+    }
+    Ok((x, y))
+}
+
+fn parse_key(name: &str) -> Result<KeyEvent, &'static str> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => return Ok(KeyEvent::Char(c)),
+        (None, _) => return Err("key needs a key name"),
+        _ => {}
+    }
+    match name {
+        "enter" => Ok(KeyEvent::Enter),
+        "bksp" | "backspace" => Ok(KeyEvent::Backspace),
+        "space" => Ok(KeyEvent::Char(' ')),
+        _ => Err("unknown key"),
+    }
+}
+
+/// Turns a byte stream into commands.
+///
+/// In line mode bytes are collected until a newline and parsed with `parse_command`.
+/// In raw mode every byte is a key press, until ctrl-c is received.
+/// Tab and the arrow keys move the button focus.
+pub struct Console {
+    line: heapless::String<MAX_LINE>,
+    raw: bool,
+    // Bytes of an escape sequence received so far, arrow keys are `ESC [ A..D`.
+    escape: u8,
+}
+
+impl Console {
+    pub const fn new() -> Self {
+        Self {
+            line: heapless::String::new(),
+            raw: false,
+            escape: 0,
+        }
+    }
+    pub fn feed(&mut self, byte: u8) -> Option<Result<ConsoleCmd, &'static str>> {
+        if self.raw {
+            if let Some(nav) = self.escape_sequence(byte) {
+                return nav.map(|nav| Ok(ConsoleCmd::Nav(nav)));
+            }
+            return match byte {
+                EXIT_RAW => {
+                    self.raw = false;
+                    None
+                }
+                b'\t' => Some(Ok(ConsoleCmd::Nav(NavEvent::Next))),
+                b'\r' | b'\n' => Some(Ok(ConsoleCmd::Key(KeyEvent::Enter))),
+                0x08 | 0x7f => Some(Ok(ConsoleCmd::Key(KeyEvent::Backspace))),
+                0x20..=0x7e => Some(Ok(ConsoleCmd::Key(KeyEvent::Char(byte as char)))),
+                _ => None,
+            };
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                if self.line.is_empty() {
+                    return None;
+                }
+                let result = parse_command(&self.line);
+                self.line.clear();
+                if result == Ok(ConsoleCmd::RawKeys) {
+                    self.raw = true;
+                }
+                Some(result)
+            }
+            0x08 | 0x7f => {
+                self.line.pop();
+                None
+            }
+            0x20..=0x7e => {
+                if self.line.push(byte as char).is_err() {
+                    self.line.clear();
+                    return Some(Err("line too long"));
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Tracks arrow key sequences. Returns `Some` when the byte was part of a sequence,
+    // with the navigation event once it is complete.
+    fn escape_sequence(&mut self, byte: u8) -> Option<Option<NavEvent>> {
+        match (self.escape, byte) {
+            (0, ESC) => self.escape = 1,
+            (0, _) => return None,
+            (1, b'[') => self.escape = 2,
+            (2, _) => {
+                self.escape = 0;
+                return Some(match byte {
+                    b'A' | b'D' => Some(NavEvent::Prev),
+                    b'B' | b'C' => Some(NavEvent::Next),
+                    _ => None,
+                });
+            }
+            // Not a sequence we know, drop it.
+            _ => self.escape = 0,
+        }
+        Some(None)
+    }
+}
+
+/// Serial console on the USB serial/JTAG port.
+///
+/// Input commands are injected into the event queue, so apps see them like real input.
+pub struct SerialConsole<'d> {
+    rx: UsbSerialJtagRx<'d, Blocking>,
+    console: Console,
+}
+
+impl<'d> SerialConsole<'d> {
+    pub fn new(rx: UsbSerialJtagRx<'d, Blocking>) -> Self {
+        Self {
+            rx,
+            console: Console::new(),
+        }
+    }
+    /// Console task body, polls the port and forwards commands to `COMMANDS`.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            while let Some(cmd) = self.poll() {
+                COMMANDS.send(cmd).await;
+                request_wake();
+            }
+            Timer::after(POLL_INTERVAL).await;
+        }
+    }
+    /// Read pending bytes. Commands that are not input, like `launch`, are returned to the caller.
+    ///
+    /// Stops at the first returned command, the remaining bytes are read on the next poll.
+    pub fn poll(&mut self) -> Option<ConsoleCmd> {
+        while let Ok(byte) = self.rx.read_byte() {
+            match self.console.feed(byte) {
+                Some(Ok(cmd)) => {
+                    if let Some(cmd) = inject(cmd) {
+                        return Some(cmd);
+                    }
+                }
+                Some(Err(e)) => error!("console: {}", e),
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+// Push input commands into the event queue, returns everything else.
+fn inject(cmd: ConsoleCmd) -> Option<ConsoleCmd> {
+    match cmd {
+        ConsoleCmd::Tap { x, y } => {
+            push_event(InputEvent::Touch(TouchEvent::Down { x, y }));
+            push_event(InputEvent::Touch(TouchEvent::Up));
+        }
+        ConsoleCmd::Swipe { from, to } => {
+            push_event(InputEvent::Touch(TouchEvent::Down {
+                x: from.0,
+                y: from.1,
+            }));
+            // Only the end point is sent, `push_event` merges consecutive moves anyway.
+            push_event(InputEvent::Touch(TouchEvent::Move { x: to.0, y: to.1 }));
+            push_event(InputEvent::Touch(TouchEvent::Up));
+        }
+        ConsoleCmd::Key(key) => push_event(InputEvent::Key(key)),
+        ConsoleCmd::Nav(nav) => push_event(InputEvent::Nav(nav)),
+        ConsoleCmd::Text(text) => {
+            for c in text.chars() {
+                push_event(InputEvent::Key(KeyEvent::Char(c)));
+            }
+        }
+        ConsoleCmd::RawKeys => info!("console: raw key mode, ctrl-c to exit"),
+        ConsoleCmd::Help => info!("{}", HELP),
+        cmd => return Some(cmd),
+    }
+    None
+}
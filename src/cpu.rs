@@ -0,0 +1,85 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_hal::peripherals::SYSTEM;
+
+use crate::power_state::{PowerMode, PowerProfile};
+
+pub use crate::power_state::CpuSpeed;
+
+struct CpuState {
+    mode: PowerMode,
+    profile: PowerProfile,
+    // Number of `CpuBoost`s alive.
+    boosts: u32,
+    speed: CpuSpeed,
+}
+
+// `esp_hal::init` starts the CPU at full speed.
+static STATE: Mutex<RefCell<CpuState>> = Mutex::new(RefCell::new(CpuState {
+    mode: PowerMode::Active,
+    profile: PowerProfile::Performance,
+    boosts: 0,
+    speed: CpuSpeed::High,
+}));
+
+/// Keeps the CPU at full speed until it is dropped, for heavy work like rendering.
+pub struct CpuBoost(());
+
+impl Drop for CpuBoost {
+    fn drop(&mut self) {
+        update(|state| state.boosts -= 1);
+    }
+}
+
+/// Run the CPU at full speed for as long as the returned `CpuBoost` is alive.
+pub fn boost() -> CpuBoost {
+    update(|state| state.boosts += 1);
+    CpuBoost(())
+}
+
+/// Switch to the clock of the power mode in `profile`, unless the CPU is boosted.
+pub fn set_power_mode(mode: PowerMode, profile: PowerProfile) {
+    update(|state| {
+        state.mode = mode;
+        state.profile = profile;
+    });
+}
+
+/// The clock of the current power mode, without boosts.
+pub fn base_speed() -> CpuSpeed {
+    critical_section::with(|cs| {
+        let state = STATE.borrow_ref(cs);
+        state.profile.cpu_speed(state.mode)
+    })
+}
+
+fn update(f: impl FnOnce(&mut CpuState)) {
+    critical_section::with(|cs| {
+        let mut state = STATE.borrow_ref_mut(cs);
+        f(&mut state);
+        let speed = match state.boosts {
+            0 => state.profile.cpu_speed(state.mode),
+            _ => CpuSpeed::High,
+        };
+        if speed != state.speed {
+            state.speed = speed;
+            write_speed(speed);
+        }
+    });
+}
+
+fn write_speed(speed: CpuSpeed) {
+    let period = match speed {
+        CpuSpeed::Low => 0,
+        CpuSpeed::Medium => 1,
+        CpuSpeed::High => 2,
+    };
+    // The PLL stays at 480 MHz and the core voltage at the level for 240 MHz,
+    // so only the divider changes. The APB clock and the timers are not affected.
+    SYSTEM::regs().cpu_per_conf().modify(|_, w| {
+        // SAFETY: 0 to 2 are the valid dividers for the 480 MHz PLL, and `update` only writes inside a
+        // critical section, so the read-modify-write is not interrupted.
+        unsafe { w.cpuperiod_sel().bits(period) }
+    });
+}
@@ -0,0 +1,201 @@
+use core::{cell::Cell, fmt::Write, panic::PanicInfo};
+
+use critical_section::Mutex;
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::Text,
+};
+use esp_hal::{ram, time::Instant};
+use heapless::String;
+
+use crate::graphics::{BASE3, CELL_H, CELL_W, RED, SCREEN_W};
+
+pub const MESSAGE_LEN: usize = 120;
+pub const LOCATION_LEN: usize = 48;
+pub const APP_LEN: usize = 16;
+
+// Marks a valid record, RTC RAM holds random data after power on.
+const MAGIC: u32 = 0x4352_5348;
+
+/// What was going on when the firmware panicked.
+pub struct CrashReport {
+    pub message: String<MESSAGE_LEN>,
+    pub location: String<LOCATION_LEN>,
+    // Name of the active app.
+    pub app: String<APP_LEN>,
+    pub uptime_ms: u64,
+}
+
+// Layout of the report in RTC RAM, only plain integers so any bit pattern is valid.
+#[repr(C)]
+struct CrashRecord {
+    magic: u32,
+    checksum: u32,
+    uptime_ms: u64,
+    message_len: u8,
+    location_len: u8,
+    app_len: u8,
+    message: [u8; MESSAGE_LEN],
+    location: [u8; LOCATION_LEN],
+    app: [u8; APP_LEN],
+}
+
+impl CrashRecord {
+    const EMPTY: Self = Self {
+        magic: 0,
+        checksum: 0,
+        uptime_ms: 0,
+        message_len: 0,
+        location_len: 0,
+        app_len: 0,
+        message: [0; MESSAGE_LEN],
+        location: [0; LOCATION_LEN],
+        app: [0; APP_LEN],
+    };
+
+    fn checksum(&self) -> u32 {
+        let lengths = [self.message_len, self.location_len, self.app_len];
+        let bytes = self
+            .message
+            .iter()
+            .chain(&self.location)
+            .chain(&self.app)
+            .chain(&lengths);
+        bytes.fold(self.uptime_ms as u32, |sum, byte| {
+            sum.rotate_left(5) ^ *byte as u32
+        })
+    }
+}
+
+// SAFETY: Only integers and arrays of integers.
+unsafe impl esp_hal::Persistable for CrashRecord {}
+
+// Survives a software reset, so the report can be shown after rebooting.
+#[ram(unstable(rtc_fast, persistent))]
+static mut LAST_CRASH: CrashRecord = CrashRecord::EMPTY;
+
+static ACTIVE_APP: Mutex<Cell<&'static str>> = Mutex::new(Cell::new(""));
+
+/// Remember the active app for the crash report.
+pub fn set_active_app(name: &'static str) {
+    critical_section::with(|cs| ACTIVE_APP.borrow(cs).set(name));
+}
+
+/// Build the report of a panic and store it in RTC RAM.
+pub fn record_panic(info: &PanicInfo) -> CrashReport {
+    let mut message = Truncate(String::new());
+    let _ = write!(message, "{}", info.message());
+    let mut location = Truncate(String::new());
+    if let Some(loc) = info.location() {
+        let _ = write!(location, "{}:{}", loc.file(), loc.line());
+    }
+    let mut app = Truncate(String::new());
+    let _ = app.write_str(critical_section::with(|cs| ACTIVE_APP.borrow(cs).get()));
+
+    let report = CrashReport {
+        message: message.0,
+        location: location.0,
+        app: app.0,
+        uptime_ms: Instant::now().duration_since_epoch().as_millis(),
+    };
+    record_report(&report);
+    report
+}
+
+/// The report of the last panic, if there was one since the device was powered on.
+pub fn last_crash() -> Option<CrashReport> {
+    // SAFETY: Only written by the panic handler, which does not return.
+    let record = unsafe { &*core::ptr::addr_of!(LAST_CRASH) };
+    if record.magic != MAGIC || record.checksum != record.checksum() {
+        return None;
+    }
+    Some(CrashReport {
+        message: from_bytes(&record.message, record.message_len),
+        location: from_bytes(&record.location, record.location_len),
+        app: from_bytes(&record.app, record.app_len),
+        uptime_ms: record.uptime_ms,
+    })
+}
+
+/// Forget the last crash report.
+pub fn clear_last_crash() {
+    // SAFETY: Only called from the UI task, the panic handler overwrites the whole record.
+    unsafe { (*core::ptr::addr_of_mut!(LAST_CRASH)).magic = 0 };
+}
+
+/// Draw the report straight to the display, without the screen grid of the main task.
+pub fn draw_panic_screen<D: DrawTarget<Color = Rgb565>>(
+    display: &mut D,
+    report: &CrashReport,
+) -> Result<(), D::Error> {
+    display.clear(RED)?;
+    let style = MonoTextStyle::new(&FONT_6X10, BASE3);
+    let cols = (SCREEN_W / CELL_W) as usize;
+    let mut y = 2 * CELL_H as i32;
+    let mut line = |text: &str, display: &mut D| -> Result<(), D::Error> {
+        // Long lines are wrapped at the screen width.
+        for chunk in text.as_bytes().chunks(cols) {
+            let chunk = core::str::from_utf8(chunk).unwrap_or("?");
+            Text::new(chunk, Point::new(0, y + FONT_6X10.baseline as i32), style).draw(display)?;
+            y += CELL_H as i32;
+        }
+        if text.is_empty() {
+            y += CELL_H as i32;
+        }
+        Ok(())
+    };
+
+    line("*** PANIC ***", display)?;
+    line("", display)?;
+    line(&report.message, display)?;
+    line("", display)?;
+    line(&report.location, display)?;
+    let uptime = heapless::format!(40; "Uptime: {} ms", report.uptime_ms).unwrap_or_default();
+    line(&uptime, display)?;
+    let app = heapless::format!(40; "App: {}", report.app).unwrap_or_default();
+    line(&app, display)?;
+    line("", display)?;
+    line("Restarting...", display)
+}
+
+/// Store a report in RTC RAM, also used for resets that were not caused by a panic.
+pub fn record_report(report: &CrashReport) {
+    let mut record = CrashRecord::EMPTY;
+    record.uptime_ms = report.uptime_ms;
+    record.message_len = copy(&mut record.message, &report.message);
+    record.location_len = copy(&mut record.location, &report.location);
+    record.app_len = copy(&mut record.app, &report.app);
+    record.checksum = record.checksum();
+    record.magic = MAGIC;
+    // SAFETY: Written by the panic handler, which does not return, or by the UI task during boot.
+    unsafe { core::ptr::addr_of_mut!(LAST_CRASH).write(record) };
+}
+
+fn copy(dst: &mut [u8], src: &str) -> u8 {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src.as_bytes()[..len]);
+    len as u8
+}
+
+fn from_bytes<const N: usize>(bytes: &[u8], len: u8) -> String<N> {
+    let bytes = &bytes[..(len as usize).min(bytes.len())];
+    let mut s = Truncate(String::new());
+    let _ = s.write_str(core::str::from_utf8(bytes).unwrap_or_default());
+    s.0
+}
+
+// Keeps as much of the text as fits instead of failing.
+struct Truncate<const N: usize>(String<N>);
+
+impl<const N: usize> Write for Truncate<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+// Timings in milliseconds.
+pub const DEBOUNCE_MS: u64 = 20;
+pub const LONG_PRESS_MS: u64 = 600;
+pub const REPEAT_MS: u64 = 150;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HwKey {
+    Boot,
+    User,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PressKind {
+    // Released before the long press threshold.
+    Short,
+    // Held for `LONG_PRESS_MS`, sent once while the key is still down.
+    Long,
+    // Sent every `REPEAT_MS` while held after a long press.
+    Repeat,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HwKeyEvent {
+    pub key: HwKey,
+    pub press: PressKind,
+}
+
+/// Debounce state machine for a single key.
+///
+/// Does not touch any hardware, feed it the raw pin state and the current time.
+pub struct Debouncer {
+    raw: bool,
+    raw_since: u64,
+    pressed: bool,
+    pressed_at: u64,
+    long_sent: bool,
+    last_repeat: u64,
+}
+
+impl Debouncer {
+    pub const fn new() -> Self {
+        Self {
+            raw: false,
+            raw_since: 0,
+            pressed: false,
+            pressed_at: 0,
+            long_sent: false,
+            last_repeat: 0,
+        }
+    }
+    pub fn update(&mut self, raw_pressed: bool, now_ms: u64) -> Option<PressKind> {
+        if raw_pressed != self.raw {
+            self.raw = raw_pressed;
+            self.raw_since = now_ms;
+        }
+
+        // Only accept a new level once it has been stable for the debounce time.
+        if self.raw != self.pressed && now_ms.saturating_sub(self.raw_since) >= DEBOUNCE_MS {
+            self.pressed = self.raw;
+            if self.pressed {
+                self.pressed_at = now_ms;
+                self.long_sent = false;
+                return None;
+            }
+            return if self.long_sent {
+                None
+            } else {
+                Some(PressKind::Short)
+            };
+        }
+
+        if self.pressed {
+            if !self.long_sent && now_ms.saturating_sub(self.pressed_at) >= LONG_PRESS_MS {
+                self.long_sent = true;
+                self.last_repeat = now_ms;
+                return Some(PressKind::Long);
+            }
+            if self.long_sent && now_ms.saturating_sub(self.last_repeat) >= REPEAT_MS {
+                self.last_repeat = now_ms;
+                return Some(PressKind::Repeat);
+            }
+        }
+        None
+    }
+    /// True while the key is (possibly) held, the caller should keep polling at a high rate.
+    pub fn is_active(&self) -> bool {
+        self.raw || self.pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Poll a pin every millisecond, `trace` holds the times at which the level changes.
+    fn run(trace: &[(u64, bool)], until_ms: u64) -> Vec<(u64, PressKind)> {
+        let mut debouncer = Debouncer::new();
+        let mut level = false;
+        let mut events = Vec::new();
+        for now in 0..=until_ms {
+            if let Some((_, changed)) = trace.iter().find(|(at, _)| *at == now) {
+                level = *changed;
+            }
+            if let Some(press) = debouncer.update(level, now) {
+                events.push((now, press));
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn bouncing_contacts_give_one_short_press() {
+        let trace = [
+            (10, true),
+            (12, false),
+            (13, true),
+            (15, false),
+            (16, true),
+            (200, false),
+            (201, true),
+            (203, false),
+        ];
+        assert_eq!(run(&trace, 400), [(223, PressKind::Short)]);
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_is_ignored() {
+        let trace = [(10, true), (10 + DEBOUNCE_MS - 1, false)];
+        assert_eq!(run(&trace, 400), []);
+    }
+
+    #[test]
+    fn held_key_sends_long_press_then_repeats() {
+        let trace = [(10, true), (1000, false)];
+        let pressed = 10 + DEBOUNCE_MS;
+        let long = pressed + LONG_PRESS_MS;
+        assert_eq!(
+            run(&trace, 1200),
+            [
+                (long, PressKind::Long),
+                (long + REPEAT_MS, PressKind::Repeat),
+                (long + 2 * REPEAT_MS, PressKind::Repeat),
+            ]
+        );
+    }
+
+    #[test]
+    fn is_active_until_release_is_debounced() {
+        let mut debouncer = Debouncer::new();
+        assert!(!debouncer.is_active());
+        debouncer.update(true, 0);
+        assert!(debouncer.is_active());
+        debouncer.update(true, DEBOUNCE_MS);
+        debouncer.update(false, 100);
+        assert!(debouncer.is_active());
+        assert_eq!(
+            debouncer.update(false, 100 + DEBOUNCE_MS),
+            Some(PressKind::Short)
+        );
+        assert!(!debouncer.is_active());
+    }
+}
@@ -10,9 +10,18 @@ use mipidsi::{
     Builder, Display, NoResetPin,
     interface::{Generic8BitBus, ParallelInterface},
     models::ST7789,
-    options::{ColorOrder, Orientation},
+    options::{ColorOrder, Orientation, Rotation},
 };
 
+pub use crate::graphics::ScreenRotation;
+
+fn orientation(rotation: ScreenRotation) -> Orientation {
+    match rotation {
+        ScreenRotation::Normal => Orientation::new(),
+        ScreenRotation::Flipped => Orientation::new().rotate(Rotation::Deg180),
+    }
+}
+
 pub struct DisplayPins {
     pub d0: esp_hal::peripherals::GPIO48<'static>,
     pub d1: esp_hal::peripherals::GPIO47<'static>,
@@ -52,6 +61,85 @@ type LcdDisplay = Display<LcdInterface, ST7789, NoResetPin>;
 pub struct DisplayDriver<'a> {
     display: LcdDisplay,
     backlight_channel: Channel<'a, LowSpeed>,
+    backlight_timer: &'a Timer<'a, LowSpeed>,
+}
+
+impl DisplayPins {
+    /// Take the display pins again after they were moved into the `DisplayDriver`.
+    ///
+    /// # Safety
+    ///
+    /// Only meant for the panic handler, the `DisplayDriver` must not be used afterwards.
+    pub unsafe fn steal() -> Self {
+        unsafe {
+            Self {
+                d0: esp_hal::peripherals::GPIO48::steal(),
+                d1: esp_hal::peripherals::GPIO47::steal(),
+                d2: esp_hal::peripherals::GPIO39::steal(),
+                d3: esp_hal::peripherals::GPIO40::steal(),
+                d4: esp_hal::peripherals::GPIO41::steal(),
+                d5: esp_hal::peripherals::GPIO42::steal(),
+                d6: esp_hal::peripherals::GPIO45::steal(),
+                d7: esp_hal::peripherals::GPIO46::steal(),
+                wr: esp_hal::peripherals::GPIO8::steal(),
+                dc: esp_hal::peripherals::GPIO7::steal(),
+                backlight: esp_hal::peripherals::GPIO38::steal(),
+                pwr_en: esp_hal::peripherals::GPIO10::steal(),
+                pwr_on: esp_hal::peripherals::GPIO14::steal(),
+            }
+        }
+    }
+}
+
+// Set up the LCD, returns the display and the backlight pin.
+fn init_lcd(
+    pins: DisplayPins,
+    output_config: OutputConfig,
+) -> Option<(LcdDisplay, Output<'static>)> {
+    // Data pins
+    let lcd_d0 = Output::new(pins.d0, Level::Low, output_config);
+    let lcd_d1 = Output::new(pins.d1, Level::Low, output_config);
+    let lcd_d2 = Output::new(pins.d2, Level::Low, output_config);
+    let lcd_d3 = Output::new(pins.d3, Level::Low, output_config);
+    let lcd_d4 = Output::new(pins.d4, Level::Low, output_config);
+    let lcd_d5 = Output::new(pins.d5, Level::Low, output_config);
+    let lcd_d6 = Output::new(pins.d6, Level::Low, output_config);
+    let lcd_d7 = Output::new(pins.d7, Level::Low, output_config);
+
+    // Control pins
+    let lcd_wr = Output::new(pins.wr, Level::High, output_config);
+    let lcd_dc = Output::new(pins.dc, Level::Low, output_config);
+
+    // Backlight
+    let backlight = Output::new(pins.backlight, Level::High, output_config);
+
+    // Power control (must be ON)
+    let _lcd_pwr_en = Output::new(pins.pwr_en, Level::High, output_config);
+    let _lcd_pwr_on = Output::new(pins.pwr_on, Level::High, output_config);
+
+    // Build bus + interface
+    let bus = Generic8BitBus::new((
+        lcd_d0, lcd_d1, lcd_d2, lcd_d3, lcd_d4, lcd_d5, lcd_d6, lcd_d7,
+    ));
+    let interface = ParallelInterface::new(bus, lcd_dc, lcd_wr);
+
+    // Init display
+    let mut delay = Delay::new();
+    let display: LcdDisplay = Builder::new(ST7789, interface)
+        .color_order(ColorOrder::Rgb)
+        .display_size(240, 320)
+        .orientation(Orientation::new())
+        .init(&mut delay)
+        .ok()?;
+
+    Some((display, backlight))
+}
+
+/// Set up the display with the backlight fully on, without the LEDC timer.
+///
+/// Used by the panic handler, which cannot rely on the state of the main task.
+pub fn init_unmanaged(pins: DisplayPins, output_config: OutputConfig) -> Option<LcdDisplay> {
+    init_lcd(pins, output_config).map(|(display, _)| display)
 }
 
 impl<'a> DisplayDriver<'a> {
@@ -60,41 +148,8 @@ impl<'a> DisplayDriver<'a> {
         output_config: OutputConfig,
         low_speed_timer: &'a Timer<'a, LowSpeed>,
     ) -> Self {
-        // Data pins
-        let lcd_d0 = Output::new(pins.d0, Level::Low, output_config);
-        let lcd_d1 = Output::new(pins.d1, Level::Low, output_config);
-        let lcd_d2 = Output::new(pins.d2, Level::Low, output_config);
-        let lcd_d3 = Output::new(pins.d3, Level::Low, output_config);
-        let lcd_d4 = Output::new(pins.d4, Level::Low, output_config);
-        let lcd_d5 = Output::new(pins.d5, Level::Low, output_config);
-        let lcd_d6 = Output::new(pins.d6, Level::Low, output_config);
-        let lcd_d7 = Output::new(pins.d7, Level::Low, output_config);
-
-        // Control pins
-        let lcd_wr = Output::new(pins.wr, Level::High, output_config);
-        let lcd_dc = Output::new(pins.dc, Level::Low, output_config);
-
-        // Backlight
-        let backlight = Output::new(pins.backlight, Level::High, output_config);
-
-        // Power control (must be ON)
-        let _lcd_pwr_en = Output::new(pins.pwr_en, Level::High, output_config);
-        let _lcd_pwr_on = Output::new(pins.pwr_on, Level::High, output_config);
-
-        // Build bus + interface
-        let bus = Generic8BitBus::new((
-            lcd_d0, lcd_d1, lcd_d2, lcd_d3, lcd_d4, lcd_d5, lcd_d6, lcd_d7,
-        ));
-        let interface = ParallelInterface::new(bus, lcd_dc, lcd_wr);
-
-        // Init display
-        let mut delay = Delay::new();
-        let display: LcdDisplay = Builder::new(ST7789, interface)
-            .color_order(ColorOrder::Rgb)
-            .display_size(240, 320)
-            .orientation(Orientation::new())
-            .init(&mut delay)
-            .unwrap();
+        let (display, backlight) =
+            init_lcd(pins, output_config).expect("Failed to initialize display");
 
         // Backlight controls
         let mut channel0: esp_hal::ledc::channel::Channel<'_, LowSpeed> =
@@ -114,6 +169,7 @@ impl<'a> DisplayDriver<'a> {
         Self {
             display,
             backlight_channel: channel0,
+            backlight_timer: low_speed_timer,
         }
     }
 
@@ -121,6 +177,34 @@ impl<'a> DisplayDriver<'a> {
         &mut self.display
     }
 
+    /// Turn the picture, the screen has to be drawn again afterwards.
+    pub fn set_rotation(&mut self, rotation: ScreenRotation) -> bool {
+        match self.display.set_orientation(orientation(rotation)) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to rotate the display: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// Put the controller to sleep or wake it up, it keeps the picture in the meantime.
+    pub fn set_sleeping(&mut self, sleeping: bool) -> bool {
+        let mut delay = Delay::new();
+        let res = if sleeping {
+            self.display.sleep(&mut delay)
+        } else {
+            self.display.wake(&mut delay)
+        };
+        match res {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to change the display sleep mode: {:?}", e);
+                false
+            }
+        }
+    }
+
     pub fn set_backlight(&mut self, brightness: u8) {
         let brightness = if brightness == 100 { 99 } else { brightness };
         let res = self.backlight_channel.set_duty(brightness);
@@ -132,5 +216,6 @@ impl<'a> DisplayDriver<'a> {
         self.backlight_channel
             .configure_hw()
             .expect("Failed to configure..");
+        self.backlight_timer.update_hw();
     }
 }
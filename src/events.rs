@@ -0,0 +1,86 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use esp_hal::time::Instant;
+use heapless::Deque;
+
+use crate::input::{self, InputSource, NavEvent};
+use crate::{keyboard::KeyEvent, keys::HwKeyEvent, touch::TouchEvent};
+
+pub const QUEUE_SIZE: usize = 64;
+
+#[derive(PartialEq)]
+pub enum InputEvent {
+    Touch(TouchEvent),
+    HwKey(HwKeyEvent),
+    Key(KeyEvent),
+    Nav(NavEvent),
+}
+
+pub struct TimedEvent {
+    pub time: Instant,
+    pub event: InputEvent,
+}
+
+// Filled from interrupt handlers and the main loop, drained once per frame.
+static QUEUE: Mutex<RefCell<Deque<TimedEvent, QUEUE_SIZE>>> =
+    Mutex::new(RefCell::new(Deque::new()));
+
+// Wakes the UI task, set when an event is queued or something else needs a new frame.
+static WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Wakes the input task from the GPIO interrupt, so it starts polling.
+static INPUT_IRQ: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Queue an input event, timestamped with the current time.
+pub fn push_event(event: InputEvent) {
+    let event = TimedEvent {
+        time: Instant::now(),
+        event,
+    };
+    critical_section::with(|cs| {
+        let mut queue = QUEUE.borrow_ref_mut(cs);
+
+        // Only the latest position of a drag is interesting, merge consecutive moves.
+        let is_move = |e: &InputEvent| matches!(e, InputEvent::Touch(TouchEvent::Move { .. }));
+        if is_move(&event.event) && queue.back().is_some_and(|last| is_move(&last.event)) {
+            queue.pop_back();
+        }
+
+        // Drop the oldest event when full, the most recent input matters most.
+        if queue.is_full() {
+            queue.pop_front();
+        }
+        let _ = queue.push_back(event);
+    });
+    WAKE.signal(());
+}
+
+pub fn pop_event() -> Option<TimedEvent> {
+    critical_section::with(|cs| QUEUE.borrow_ref_mut(cs).pop_front())
+}
+
+pub fn has_pending_events() -> bool {
+    critical_section::with(|cs| !QUEUE.borrow_ref(cs).is_empty())
+}
+
+/// Wake the UI task from its frame wait.
+pub fn request_wake() {
+    WAKE.signal(());
+}
+
+/// Wait until an event is queued or `request_wake` is called.
+pub async fn wait_for_wake() {
+    WAKE.wait().await
+}
+
+/// Called from interrupt handlers when an input line changed.
+pub fn notify_input_irq() {
+    INPUT_IRQ.signal(());
+}
+
+/// Input task body, see `input::run_input`.
+pub async fn run_input(source: impl InputSource) -> ! {
+    input::run_input(source, &INPUT_IRQ).await
+}
@@ -0,0 +1,53 @@
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_hal::peripherals::FLASH;
+use esp_storage::FlashStorage;
+
+use crate::journal::{BlockDevice, BlockError};
+
+// The `storage` partition in partitions.csv.
+const PARTITION_OFFSET: u32 = 0x40_0000;
+const PARTITION_SIZE: u32 = 0x4_0000;
+
+/// The storage partition of the internal flash.
+pub struct FlashPartition<'d> {
+    flash: FlashStorage<'d>,
+}
+
+impl<'d> FlashPartition<'d> {
+    pub fn new(flash: FLASH<'d>) -> Self {
+        Self {
+            flash: FlashStorage::new(flash),
+        }
+    }
+    // Offset in the flash, when `len` bytes at `offset` are inside the partition.
+    fn address(offset: u32, len: usize) -> Result<u32, BlockError> {
+        match offset.checked_add(len as u32) {
+            Some(end) if end <= PARTITION_SIZE => Ok(PARTITION_OFFSET + offset),
+            _ => Err(BlockError::OutOfBounds),
+        }
+    }
+}
+
+impl BlockDevice for FlashPartition<'_> {
+    fn block_size(&self) -> u32 {
+        FlashStorage::ERASE_SIZE as u32
+    }
+    fn block_count(&self) -> u32 {
+        PARTITION_SIZE / self.block_size()
+    }
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+        let address = Self::address(offset, buf.len())?;
+        ReadNorFlash::read(&mut self.flash, address, buf).map_err(|_| BlockError::Device)
+    }
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlockError> {
+        let address = Self::address(offset, data.len())?;
+        NorFlash::write(&mut self.flash, address, data).map_err(|_| BlockError::Device)
+    }
+    fn erase(&mut self, block: u32) -> Result<(), BlockError> {
+        let size = self.block_size();
+        let address = Self::address(block * size, size as usize)?;
+        self.flash
+            .erase(address, address + size)
+            .map_err(|_| BlockError::Device)
+    }
+}
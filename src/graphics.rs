@@ -8,6 +8,8 @@ use embedded_graphics::{
 };
 use log::error;
 
+use crate::{battery::BatteryState, calendar::DateTime};
+
 // Background / base tones
 pub const BASE03: Rgb565 = Rgb565::new(0, 11, 7); // #002b36
 pub const BASE02: Rgb565 = Rgb565::new(1, 13, 8); // #073642
@@ -28,12 +30,88 @@ pub const BLUE: Rgb565 = Rgb565::new(5, 34, 26); // #268bd2
 pub const CYAN: Rgb565 = Rgb565::new(5, 40, 18); // #2aa198
 pub const GREEN: Rgb565 = Rgb565::new(16, 38, 0); // #859900
 
+// The light theme swaps the base tones, accent colors stay the same.
+const LIGHT_TONES: [(Rgb565, Rgb565); 8] = [
+    (BASE03, BASE3),
+    (BASE02, BASE2),
+    (BASE01, BASE1),
+    (BASE00, BASE0),
+    (BASE0, BASE00),
+    (BASE1, BASE01),
+    (BASE2, BASE02),
+    (BASE3, BASE03),
+];
+
+/// Color scheme of the screen, apps always draw with the dark colors.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    // In the order of their stored value.
+    pub const ALL: [Theme; 2] = [Theme::Dark, Theme::Light];
+
+    pub fn next(self) -> Self {
+        match self {
+            Theme::Dark => Theme::Light,
+            Theme::Light => Theme::Dark,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+    /// Color shown on the display for a color drawn by an app.
+    pub fn map(self, color: Rgb565) -> Rgb565 {
+        match self {
+            Theme::Dark => color,
+            Theme::Light => LIGHT_TONES
+                .iter()
+                .find(|(dark, _)| *dark == color)
+                .map_or(color, |(_, light)| *light),
+        }
+    }
+}
+
 pub const SCREEN_W: u16 = 240;
 pub const SCREEN_H: u16 = 320;
 
 pub const CELL_W: u16 = 6;
 pub const CELL_H: u16 = 10;
 
+// Longest toast message, one row of text.
+pub const TOAST_LEN: usize = (SCREEN_W / CELL_W) as usize;
+
+/// Which way up the device is held, the touch coordinates are turned with the screen.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ScreenRotation {
+    Normal,
+    // Turned 180 degrees, the USB port is on top.
+    Flipped,
+}
+
+impl ScreenRotation {
+    // In the order of their stored value.
+    pub const ALL: [ScreenRotation; 2] = [ScreenRotation::Normal, ScreenRotation::Flipped];
+
+    pub fn next(self) -> Self {
+        match self {
+            ScreenRotation::Normal => ScreenRotation::Flipped,
+            ScreenRotation::Flipped => ScreenRotation::Normal,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            ScreenRotation::Normal => "Normal",
+            ScreenRotation::Flipped => "Flipped",
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Cell {
     pub ch: char,
@@ -57,12 +135,33 @@ pub struct ScreenGrid<'a> {
     pub cols: u16,
     pub rows: u16,
     pub cells: &'a mut [Cell],
+    theme: Theme,
 }
 
 impl<'a> ScreenGrid<'a> {
     pub fn new(cols: u16, rows: u16, cells: &'a mut [Cell]) -> Self {
         // caller ensures cells.len() == cols as usize * rows as usize
-        Self { cols, rows, cells }
+        Self {
+            cols,
+            rows,
+            cells,
+            theme: Theme::Dark,
+        }
+    }
+
+    pub fn theme(&self) -> Theme {
+        self.theme
+    }
+    /// Change the theme, the whole screen is drawn again.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.invalidate();
+    }
+    /// Draw every cell again with the next render, when the display lost its contents.
+    pub fn invalidate(&mut self) {
+        for cell in self.cells.iter_mut() {
+            cell.dirty = true;
+        }
     }
 
     // Get cell index based on grid position
@@ -132,7 +231,34 @@ pub fn screen_pos_to_grid_pos(x: u16, y: u16) -> (u16, u16) {
     )
 }
 
-pub fn draw_status_bars(grid: &mut ScreenGrid, app_name: &str, render_time: u64) {
+/// What the status bar shows besides the render time, it is redrawn when this changes.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct StatusInfo {
+    // Hour and minute of the local time, `None` while the clock is not set.
+    pub time: Option<(u8, u8)>,
+    // Charge in percent, `None` without a battery.
+    pub battery: Option<u8>,
+    pub charging: bool,
+    pub battery_low: bool,
+}
+
+impl StatusInfo {
+    pub fn new(clock: Option<DateTime>, battery: Option<BatteryState>) -> Self {
+        Self {
+            time: clock.map(|time| (time.hour, time.minute)),
+            battery: battery.map(|b| b.percent),
+            charging: battery.is_some_and(|b| b.charging),
+            battery_low: battery.is_some_and(|b| b.low),
+        }
+    }
+}
+
+pub fn draw_status_bars(
+    grid: &mut ScreenGrid,
+    app_name: &str,
+    render_time: u64,
+    status: &StatusInfo,
+) {
     // Title bar
     grid.draw_box(0, 0, 40, 2, BASE00);
     grid.center_str(0, app_name, BASE3, BASE00);
@@ -146,6 +272,29 @@ pub fn draw_status_bars(grid: &mut ScreenGrid, app_name: &str, render_time: u64)
         BASE2,
         CYAN,
     );
+    if let Some(percent) = status.battery {
+        // A battery with four bars, then the percentage.
+        let mut text: heapless::String<11> = heapless::String::new();
+        let _ = text.push(if status.charging { '+' } else { ' ' });
+        let _ = text.push('[');
+        let bars = (percent as usize).div_ceil(25);
+        for idx in 0..4 {
+            let _ = text.push(if idx < bars { '|' } else { ' ' });
+        }
+        let _ = text.push(']');
+        let _ = text.push_str(&heapless::format!(4; "{:>3}%", percent).unwrap_or_default());
+        let fg = if status.battery_low { RED } else { BASE3 };
+        grid.write_str(23, 31, &text, fg, CYAN);
+    }
+    if let Some((hour, minute)) = status.time {
+        grid.write_str(
+            35,
+            31,
+            &heapless::format!(5; "{:02}:{:02}", hour, minute).unwrap_or_default(),
+            BASE3,
+            CYAN,
+        );
+    }
 }
 
 pub fn render_grid<D: DrawTarget<Color = Rgb565>>(
@@ -166,13 +315,13 @@ pub fn render_grid<D: DrawTarget<Color = Rgb565>>(
                     Size::new(CELL_W as u32, CELL_H as u32),
                 )
                 .into_styled(embedded_graphics::primitives::PrimitiveStyle::with_fill(
-                    cell.bg,
+                    grid.theme.map(cell.bg),
                 ))
                 .draw(display)?;
 
                 // Draw character
                 if cell.ch != ' ' {
-                    let style = MonoTextStyle::new(&FONT_6X10, cell.fg);
+                    let style = MonoTextStyle::new(&FONT_6X10, grid.theme.map(cell.fg));
 
                     let mut buf = [0u8; 4]; // a char can be up to 4 UTF-8 bytes
                     let s = cell.ch.encode_utf8(&mut buf);
@@ -1,18 +1,41 @@
-use crate::{
-    graphics::{BASE01, BASE3, ScreenGrid, screen_pos_to_grid_pos},
-    touch::TouchEvent,
-};
+use crate::graphics::{BASE01, BASE03, BASE3, ScreenGrid, YELLOW, screen_pos_to_grid_pos};
 use core::u16;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
 use heapless::index_map::FnvIndexMap;
 
+// Poll rate of the input task while a touch or key is held.
+pub const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
 pub type ButtonId = &'static str;
 
+pub const MAX_BUTTONS: usize = 64;
+
+#[derive(PartialEq)]
+pub enum TouchEvent {
+    Down { x: u16, y: u16 },
+    Move { x: u16, y: u16 },
+    Up,
+}
+
 #[derive(PartialEq)]
 pub enum ButtonEvent {
     Down(ButtonId),
     Up(ButtonId),
 }
 
+/// Focus navigation, for driving the UI without touching the screen.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum NavEvent {
+    Next,
+    Prev,
+    Activate,
+}
+
+/// Every button slot of the `ButtonManager` is taken.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TooManyButtons;
+
 #[derive(Debug)]
 pub struct Rect {
     pub x_min: u16,
@@ -29,7 +52,13 @@ impl Rect {
 
 pub struct ButtonManager {
     pub active_button: Option<ButtonId>,
-    pub buttons: FnvIndexMap<ButtonId, Rect, 16>,
+    // Buttons are focused in the order they were registered.
+    pub focused_button: Option<ButtonId>,
+    pub buttons: FnvIndexMap<ButtonId, Rect, MAX_BUTTONS>,
+    // Buttons with an icon show it in the middle and the label on the bottom row.
+    icons: FnvIndexMap<ButtonId, char, 16>,
+    // Text drawn instead of the id.
+    labels: FnvIndexMap<ButtonId, &'static str, MAX_BUTTONS>,
     dirty: bool,
 }
 
@@ -37,18 +66,68 @@ impl ButtonManager {
     pub fn new() -> Self {
         Self {
             active_button: None,
-            buttons: FnvIndexMap::<ButtonId, Rect, 16>::new(),
+            focused_button: None,
+            buttons: FnvIndexMap::<ButtonId, Rect, MAX_BUTTONS>::new(),
+            icons: FnvIndexMap::<ButtonId, char, 16>::new(),
+            labels: FnvIndexMap::<ButtonId, &'static str, MAX_BUTTONS>::new(),
             dirty: false,
         }
     }
-    pub fn register_button(&mut self, name: ButtonId, rect: Rect) {
+    pub fn register_button(&mut self, name: ButtonId, rect: Rect) -> Result<(), TooManyButtons> {
         self.buttons
             .insert(name, rect)
-            .expect("Failed to add button");
+            .map_err(|_| TooManyButtons)?;
         self.dirty = true;
+        Ok(())
+    }
+    /// Register a button drawn as a tile with `icon` above its label.
+    pub fn register_icon_button(
+        &mut self,
+        name: ButtonId,
+        icon: char,
+        rect: Rect,
+    ) -> Result<(), TooManyButtons> {
+        self.register_button(name, rect)?;
+        if self.icons.insert(name, icon).is_err() {
+            self.remove_button(name);
+            return Err(TooManyButtons);
+        }
+        Ok(())
+    }
+    /// Register a button that shows `label` instead of its id.
+    pub fn register_labeled_button(
+        &mut self,
+        name: ButtonId,
+        label: &'static str,
+        rect: Rect,
+    ) -> Result<(), TooManyButtons> {
+        self.register_button(name, rect)?;
+        if self.labels.insert(name, label).is_err() {
+            self.remove_button(name);
+            return Err(TooManyButtons);
+        }
+        Ok(())
+    }
+    /// Buttons that can still be registered.
+    pub fn free_slots(&self) -> usize {
+        MAX_BUTTONS - self.buttons.len()
+    }
+    pub fn remove_button(&mut self, name: ButtonId) {
+        self.icons.remove(name);
+        self.labels.remove(name);
+        if self.buttons.remove(name).is_some() {
+            if self.active_button == Some(name) {
+                self.active_button = None;
+            }
+            if self.focused_button == Some(name) {
+                self.focused_button = None;
+            }
+            self.dirty = true;
+        }
     }
     pub fn register_default_buttons(&mut self) {
-        self.register_button(
+        // Only registered after `clear`, so there is room.
+        let _ = self.register_button(
             "BACK",
             Rect {
                 x_min: 0,
@@ -60,8 +139,16 @@ impl ButtonManager {
     }
     pub fn clear(&mut self) {
         self.buttons.clear();
+        self.icons.clear();
+        self.labels.clear();
+        self.focused_button = None;
     }
     pub fn update(&mut self, touch_event: &TouchEvent) -> Option<ButtonEvent> {
+        // Touching the screen hides the focus highlight.
+        if matches!(touch_event, TouchEvent::Down { .. }) && self.focused_button.take().is_some() {
+            self.dirty = true;
+        }
+
         match touch_event {
             TouchEvent::Down { x, y } | TouchEvent::Move { x, y } => {
                 for (id, rect) in &self.buttons {
@@ -83,6 +170,31 @@ impl ButtonManager {
 
         None
     }
+    /// Move the focus or activate the focused button, activating returns `ButtonEvent::Up`.
+    pub fn navigate(&mut self, nav: NavEvent) -> Option<ButtonEvent> {
+        let count = self.buttons.len();
+        if count == 0 {
+            return None;
+        }
+        let current = self
+            .focused_button
+            .and_then(|id| self.buttons.get_index_of(id));
+
+        let next = match (nav, current) {
+            (NavEvent::Activate, _) => return self.focused_button.map(ButtonEvent::Up),
+            (NavEvent::Next, Some(idx)) => (idx + 1) % count,
+            (NavEvent::Next, None) => 0,
+            (NavEvent::Prev, Some(idx)) => (idx + count - 1) % count,
+            (NavEvent::Prev, None) => count - 1,
+        };
+
+        self.focused_button = self.buttons.get_index(next).map(|(id, _)| *id);
+        self.dirty = true;
+        None
+    }
+    pub fn has_focus(&self) -> bool {
+        self.focused_button.is_some()
+    }
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
@@ -91,20 +203,102 @@ impl ButtonManager {
             let min = screen_pos_to_grid_pos(button.1.x_min, button.1.y_min);
             let max = screen_pos_to_grid_pos(button.1.x_max, button.1.y_max);
 
-            let (fg, bg) = if let Some(active) = self.active_button {
-                if active == *button.0 {
-                    (BASE01, BASE3)
-                } else {
-                    (BASE3, BASE01)
-                }
+            let (fg, bg) = if self.active_button == Some(*button.0) {
+                (BASE01, BASE3)
+            } else if self.focused_button == Some(*button.0) {
+                (BASE03, YELLOW)
             } else {
                 (BASE3, BASE01)
             };
 
             grid.draw_box(min.0, min.1, max.0 - min.0, max.1 - min.1, bg);
-            grid.write_str(min.0, min.1, button.0, fg, bg);
+            let label = self.labels.get(button.0).copied().unwrap_or(button.0);
+            if let Some(icon) = self.icons.get(button.0) {
+                let width = max.0 - min.0;
+                let label_x = min.0 + width.saturating_sub(label.len() as u16) / 2;
+                grid.put_char(min.0 + width / 2, (min.1 + max.1) / 2 - 1, *icon, fg, bg);
+                grid.write_str(label_x, max.1 - 1, label, fg, bg);
+            } else {
+                grid.write_str(min.0, min.1, label, fg, bg);
+            }
         }
 
         self.dirty = false;
     }
 }
+
+/// The inputs sampled by the input task, the device polls the touch controller and the keys.
+pub trait InputSource {
+    /// Sample the inputs and queue their events, returns true while any input is held.
+    fn poll(&mut self) -> bool;
+}
+
+impl<F: FnMut() -> bool> InputSource for F {
+    fn poll(&mut self) -> bool {
+        self()
+    }
+}
+
+/// Input task body.
+///
+/// Held inputs are polled at `INPUT_POLL_INTERVAL` for moves and long presses,
+/// otherwise the task sleeps until `irq` is signalled by an input interrupt.
+pub async fn run_input(
+    mut source: impl InputSource,
+    irq: &Signal<CriticalSectionRawMutex, ()>,
+) -> ! {
+    loop {
+        if source.poll() {
+            Timer::after(INPUT_POLL_INTERVAL).await;
+        } else {
+            irq.wait().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_time::{advance, run_task, settle};
+    use core::cell::Cell;
+
+    #[test]
+    fn held_input_is_polled_until_released() {
+        let polls = Cell::new(0);
+        let held = Cell::new(true);
+        let irq = Signal::new();
+        let source = || {
+            polls.set(polls.get() + 1);
+            held.get()
+        };
+        run_task(run_input(source, &irq), async {
+            assert_eq!(polls.get(), 1);
+            advance(INPUT_POLL_INTERVAL.as_millis()).await;
+            advance(INPUT_POLL_INTERVAL.as_millis()).await;
+            assert_eq!(polls.get(), 3);
+
+            // After the release the task waits for the interrupt, however long it takes.
+            held.set(false);
+            advance(INPUT_POLL_INTERVAL.as_millis()).await;
+            assert_eq!(polls.get(), 4);
+            advance(1000).await;
+            assert_eq!(polls.get(), 4);
+        });
+    }
+
+    #[test]
+    fn interrupt_starts_polling() {
+        let polls = Cell::new(0);
+        let irq = Signal::new();
+        let source = || {
+            polls.set(polls.get() + 1);
+            false
+        };
+        run_task(run_input(source, &irq), async {
+            assert_eq!(polls.get(), 1);
+            irq.signal(());
+            settle().await;
+            assert_eq!(polls.get(), 2);
+        });
+    }
+}
@@ -0,0 +1,853 @@
+use heapless::{Deque, String, Vec, index_set::FnvIndexSet};
+use log::{error, info, warn};
+use mem_fs::MemFs;
+
+// Marks a block that belongs to the journal.
+const BLOCK_MAGIC: u32 = 0x4d46_534a;
+// Magic, sequence number and the CRC of the sequence number.
+const BLOCK_HEADER_LEN: u32 = 12;
+
+// Path length, a zero byte, data length (u16) and the CRC of the record.
+const RECORD_HEADER_LEN: usize = 8;
+pub const MAX_PATH_LEN: usize = 32;
+pub const MAX_DATA_LEN: usize = 256;
+const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + MAX_PATH_LEN + MAX_DATA_LEN;
+
+// Larger devices are only used partly.
+const MAX_BLOCKS: usize = 64;
+// Paths that `collect` keeps in RAM, with more files it reads the blocks again for each path.
+const MAX_FILES: usize = 64;
+// Files written since the last `JournalFs::commit`.
+const MAX_PENDING: usize = 16;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BlockError {
+    OutOfBounds,
+    Device,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum JournalError {
+    Device(BlockError),
+    TooLarge,
+    // The live files do not fit on the device anymore.
+    Full,
+    // Too many files were written since the last `JournalFs::commit`.
+    Pending,
+    Fs,
+}
+
+/// Storage that is erased in blocks, like NOR flash.
+///
+/// Erased bytes read as 0xFF and a write can only clear bits.
+/// Offsets and lengths of reads and writes are multiples of 4, and never cross a block.
+pub trait BlockDevice {
+    /// Bytes per erase block.
+    fn block_size(&self) -> u32;
+    fn block_count(&self) -> u32;
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlockError>;
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlockError>;
+    fn erase(&mut self, block: u32) -> Result<(), BlockError>;
+}
+
+/// A `BlockDevice` in RAM, behaves like flash so the journal can be run on the host.
+pub struct RamDevice<const BLOCK_SIZE: usize, const BLOCKS: usize> {
+    data: [[u8; BLOCK_SIZE]; BLOCKS],
+}
+
+impl<const BLOCK_SIZE: usize, const BLOCKS: usize> RamDevice<BLOCK_SIZE, BLOCKS> {
+    pub const fn new() -> Self {
+        Self {
+            data: [[0xff; BLOCK_SIZE]; BLOCKS],
+        }
+    }
+    pub fn bytes(&self) -> &[u8] {
+        self.data.as_flattened()
+    }
+}
+
+impl<const BLOCK_SIZE: usize, const BLOCKS: usize> Default for RamDevice<BLOCK_SIZE, BLOCKS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const BLOCK_SIZE: usize, const BLOCKS: usize> BlockDevice for RamDevice<BLOCK_SIZE, BLOCKS> {
+    fn block_size(&self) -> u32 {
+        BLOCK_SIZE as u32
+    }
+    fn block_count(&self) -> u32 {
+        BLOCKS as u32
+    }
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+        let start = offset as usize;
+        let bytes = self
+            .bytes()
+            .get(start..start + buf.len())
+            .ok_or(BlockError::OutOfBounds)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlockError> {
+        let start = offset as usize;
+        let bytes = self
+            .data
+            .as_flattened_mut()
+            .get_mut(start..start + data.len())
+            .ok_or(BlockError::OutOfBounds)?;
+        for (byte, new) in bytes.iter_mut().zip(data) {
+            *byte &= new;
+        }
+        Ok(())
+    }
+    fn erase(&mut self, block: u32) -> Result<(), BlockError> {
+        self.data
+            .get_mut(block as usize)
+            .ok_or(BlockError::OutOfBounds)?
+            .fill(0xff);
+        Ok(())
+    }
+}
+
+enum Entry {
+    // Path and data are in the buffer after the header.
+    Record {
+        path_len: usize,
+        data_len: usize,
+        len: u32,
+    },
+    // Erased, or no room for another record.
+    End,
+    // Torn by a power loss, nothing after it in the block can be trusted.
+    Invalid,
+}
+
+/// Log of file writes on a `BlockDevice`.
+///
+/// Every write appends a CRC checked record to the newest block. When the device is full, the files of
+/// the oldest block that were not written again are copied to the newest block before the oldest block
+/// is erased. Blocks are used in turn, so every block is erased about as often.
+///
+/// A record only counts when its CRC matches, so a write that is cut off by a power loss is ignored and
+/// the previous version of the file is loaded instead.
+///
+/// A record without data removes the file. It is dropped when its block is collected, as the older
+/// versions of the file are gone by then.
+pub struct Journal {
+    device: &'static mut dyn BlockDevice,
+    block_size: u32,
+    blocks: u32,
+    // Blocks in use with their sequence number, oldest first.
+    used: Deque<(u32, u32), MAX_BLOCKS>,
+    // Where the next record goes in the newest block, `None` when it cannot take more records.
+    head: Option<u32>,
+}
+
+impl Journal {
+    /// Write the files of the journal on `device` to `fs`.
+    pub fn mount(
+        device: &'static mut dyn BlockDevice,
+        fs: &mut MemFs,
+    ) -> Result<Self, JournalError> {
+        let block_size = device.block_size();
+        let blocks = device.block_count().min(MAX_BLOCKS as u32);
+        if blocks < 2 || block_size < BLOCK_HEADER_LEN + MAX_RECORD_LEN as u32 {
+            return Err(JournalError::TooLarge);
+        }
+        let mut journal = Self {
+            device,
+            block_size,
+            blocks,
+            used: Deque::new(),
+            head: None,
+        };
+
+        let mut found: Vec<(u32, u32), MAX_BLOCKS> = Vec::new();
+        for block in 0..blocks {
+            if let Some(seq) = journal.read_block_header(block)? {
+                let _ = found.push((seq, block));
+            }
+        }
+        found.sort_unstable();
+
+        let mut buf = [0; MAX_RECORD_LEN];
+        let mut files = 0;
+        for (seq, block) in found {
+            let _ = journal.used.push_back((block, seq));
+            let mut offset = BLOCK_HEADER_LEN;
+            journal.head = loop {
+                match journal.read_entry(block, offset, &mut buf)? {
+                    Entry::Record {
+                        path_len,
+                        data_len,
+                        len,
+                    } => {
+                        offset += len;
+                        let (path, data) = buf[RECORD_HEADER_LEN..].split_at(path_len);
+                        let Ok(path) = core::str::from_utf8(path) else {
+                            continue;
+                        };
+                        // Only an older version has to be removed.
+                        if data_len == 0 && fs.read(path).is_none() {
+                            continue;
+                        }
+                        if fs.write(path, &data[..data_len]).is_err() {
+                            warn!("Failed to load {}", path);
+                        }
+                        files += 1;
+                    }
+                    Entry::End => break Some(offset),
+                    Entry::Invalid => {
+                        warn!(
+                            "Journal block {} has an invalid record at {}",
+                            block, offset
+                        );
+                        break None;
+                    }
+                }
+            };
+        }
+        info!(
+            "Journal mounted, {} records in {} of {} blocks",
+            files,
+            journal.used.len(),
+            blocks
+        );
+
+        // A power loss during `collect` leaves every block in use, with some of the copies in the newest
+        // block. Finish it before anything else is appended, while that block has room for the rest.
+        if journal.used.len() as u32 == blocks {
+            warn!("Journal has no free block, collecting the oldest one");
+            if journal.head.is_none() {
+                // A copy was cut off, but the oldest block still has every file it copied.
+                journal.used.pop_back();
+                journal.open_block()?;
+            }
+            if let Err(err) = journal.collect(fs) {
+                error!("Failed to collect the oldest block: {:?}", err);
+            }
+        }
+        Ok(journal)
+    }
+
+    /// Store a new version of the file at `path`, `fs` has the current version of every file.
+    pub fn append(&mut self, path: &str, data: &[u8], fs: &MemFs) -> Result<(), JournalError> {
+        if path.is_empty() || path.len() > MAX_PATH_LEN || data.len() > MAX_DATA_LEN {
+            return Err(JournalError::TooLarge);
+        }
+        // Each round frees a block, so give up when the live files fill every block.
+        for _ in 0..self.blocks {
+            if self.write_record(path, data)? {
+                return Ok(());
+            }
+            self.open_block()?;
+            // Keep a block free to copy the live files into.
+            if self.used.len() as u32 == self.blocks {
+                self.collect(fs)?;
+            }
+        }
+        Err(JournalError::Full)
+    }
+
+    /// Bytes written to the device and its size, the written bytes include old versions of files.
+    pub fn usage(&self) -> (u32, u32) {
+        let used = match self.used.len() as u32 {
+            0 => 0,
+            blocks => (blocks - 1) * self.block_size + self.head.unwrap_or(self.block_size),
+        };
+        (used, self.blocks * self.block_size)
+    }
+
+    /// Erase every block.
+    pub fn format(&mut self) -> Result<(), JournalError> {
+        self.used.clear();
+        self.head = None;
+        for block in 0..self.blocks {
+            self.device.erase(block).map_err(JournalError::Device)?;
+        }
+        Ok(())
+    }
+
+    // Append a record to the newest block, returns false when it does not fit.
+    fn write_record(&mut self, path: &str, data: &[u8]) -> Result<bool, JournalError> {
+        let (Some(offset), Some(&(block, _))) = (self.head, self.used.back()) else {
+            return Ok(false);
+        };
+        let len = record_len(path.len(), data.len());
+        if offset + len > self.block_size {
+            return Ok(false);
+        }
+
+        let mut record: Vec<u8, MAX_RECORD_LEN> = Vec::new();
+        let _ = record.push(path.len() as u8);
+        let _ = record.push(0);
+        let _ = record.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        let crc = crc32(record.iter().chain(path.as_bytes()).chain(data));
+        let _ = record.extend_from_slice(&crc.to_le_bytes());
+        let _ = record.extend_from_slice(path.as_bytes());
+        let _ = record.extend_from_slice(data);
+        let _ = record.resize(len as usize, 0xff);
+
+        // A failed write may have changed the rest of the block, so never write there again.
+        self.head = None;
+        self.device
+            .write(block * self.block_size + offset, &record)
+            .map_err(JournalError::Device)?;
+        self.head = Some(offset + len);
+        Ok(true)
+    }
+
+    // Erase the next free block and make it the newest block.
+    fn open_block(&mut self) -> Result<(), JournalError> {
+        let (start, seq) = self
+            .used
+            .back()
+            .map_or((0, 1), |&(block, seq)| (block + 1, seq.wrapping_add(1)));
+        let block = (0..self.blocks)
+            .map(|idx| (start + idx) % self.blocks)
+            .find(|block| !self.used.iter().any(|(used, _)| used == block))
+            .ok_or(JournalError::Full)?;
+
+        self.head = None;
+        self.device.erase(block).map_err(JournalError::Device)?;
+        let mut header = [0; BLOCK_HEADER_LEN as usize];
+        header[0..4].copy_from_slice(&BLOCK_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&seq.to_le_bytes());
+        header[8..12].copy_from_slice(&crc32(&seq.to_le_bytes()).to_le_bytes());
+        self.device
+            .write(block * self.block_size, &header)
+            .map_err(JournalError::Device)?;
+
+        let _ = self.used.push_back((block, seq));
+        self.head = Some(BLOCK_HEADER_LEN);
+        Ok(())
+    }
+
+    // Copy the files of the oldest block that were not written since to the newest block, then erase it.
+    // A power loss before the erase only leaves older copies, which are loaded first.
+    fn collect(&mut self, fs: &MemFs) -> Result<(), JournalError> {
+        let Some(&(oldest, _)) = self.used.front() else {
+            return Err(JournalError::Full);
+        };
+        let mut newer = self.newer_paths()?;
+        let mut buf = [0; MAX_RECORD_LEN];
+        let mut offset = BLOCK_HEADER_LEN;
+        while let Entry::Record { path_len, len, .. } = self.read_entry(oldest, offset, &mut buf)? {
+            offset += len;
+            let path = &buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + path_len];
+            let Some(path) = core::str::from_utf8(path)
+                .ok()
+                .and_then(|path| String::<MAX_PATH_LEN>::try_from(path).ok())
+            else {
+                continue;
+            };
+            let written = match &newer {
+                Some(paths) => paths.contains(&path),
+                None => self.written_since(&path)?,
+            };
+            if written {
+                continue;
+            }
+            // Removed files are not copied.
+            let Some(data) = fs.read(&path).filter(|data| !data.is_empty()) else {
+                continue;
+            };
+            if !self.write_record(&path, data)? {
+                error!("Journal is full");
+                return Err(JournalError::Full);
+            }
+            // The oldest block can have more versions of the file, the newest one is copied already.
+            if let Some(paths) = &mut newer
+                && paths.insert(path).is_err()
+            {
+                newer = None;
+            }
+        }
+
+        self.used.pop_front();
+        self.device.erase(oldest).map_err(JournalError::Device)
+    }
+
+    // Paths with a record in a block after the oldest one, `None` when there are more than `MAX_FILES`.
+    fn newer_paths(
+        &mut self,
+    ) -> Result<Option<FnvIndexSet<String<MAX_PATH_LEN>, MAX_FILES>>, JournalError> {
+        let mut paths = FnvIndexSet::new();
+        let mut buf = [0; MAX_RECORD_LEN];
+        for block in self.newer_blocks() {
+            let mut offset = BLOCK_HEADER_LEN;
+            while let Entry::Record { path_len, len, .. } =
+                self.read_entry(block, offset, &mut buf)?
+            {
+                offset += len;
+                let path = &buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + path_len];
+                let Some(path) = core::str::from_utf8(path)
+                    .ok()
+                    .and_then(|path| String::try_from(path).ok())
+                else {
+                    continue;
+                };
+                if paths.insert(path).is_err() {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(paths))
+    }
+
+    // Whether a block after the oldest one has a record for `path`.
+    fn written_since(&mut self, path: &str) -> Result<bool, JournalError> {
+        let mut buf = [0; MAX_RECORD_LEN];
+        for block in self.newer_blocks() {
+            let mut offset = BLOCK_HEADER_LEN;
+            while let Entry::Record { path_len, len, .. } =
+                self.read_entry(block, offset, &mut buf)?
+            {
+                offset += len;
+                if &buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + path_len] == path.as_bytes() {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn newer_blocks(&self) -> Vec<u32, MAX_BLOCKS> {
+        self.used.iter().skip(1).map(|(block, _)| *block).collect()
+    }
+
+    // Sequence number of a block that belongs to the journal.
+    fn read_block_header(&mut self, block: u32) -> Result<Option<u32>, JournalError> {
+        let mut header = [0; BLOCK_HEADER_LEN as usize];
+        self.device
+            .read(block * self.block_size, &mut header)
+            .map_err(JournalError::Device)?;
+        let word = |idx: usize| {
+            u32::from_le_bytes([
+                header[idx],
+                header[idx + 1],
+                header[idx + 2],
+                header[idx + 3],
+            ])
+        };
+        let valid = word(0) == BLOCK_MAGIC && word(8) == crc32(&header[4..8]);
+        Ok(valid.then(|| word(4)))
+    }
+
+    // Read the record at `offset` into `buf`.
+    fn read_entry(
+        &mut self,
+        block: u32,
+        offset: u32,
+        buf: &mut [u8; MAX_RECORD_LEN],
+    ) -> Result<Entry, JournalError> {
+        let start = block * self.block_size;
+        if offset + RECORD_HEADER_LEN as u32 > self.block_size {
+            return Ok(Entry::End);
+        }
+        let (header, body) = buf.split_at_mut(RECORD_HEADER_LEN);
+        self.device
+            .read(start + offset, header)
+            .map_err(JournalError::Device)?;
+        if header.iter().all(|byte| *byte == 0xff) {
+            return Ok(Entry::End);
+        }
+
+        let path_len = header[0] as usize;
+        let data_len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let len = record_len(path_len, data_len);
+        if path_len == 0
+            || path_len > MAX_PATH_LEN
+            || header[1] != 0
+            || data_len > MAX_DATA_LEN
+            || offset + len > self.block_size
+        {
+            return Ok(Entry::Invalid);
+        }
+        let body_len = len as usize - RECORD_HEADER_LEN;
+        self.device
+            .read(
+                start + offset + RECORD_HEADER_LEN as u32,
+                &mut body[..body_len],
+            )
+            .map_err(JournalError::Device)?;
+
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if crc32(header[..4].iter().chain(&body[..path_len + data_len])) != crc {
+            return Ok(Entry::Invalid);
+        }
+        Ok(Entry::Record {
+            path_len,
+            data_len,
+            len,
+        })
+    }
+}
+
+// Records are padded to a multiple of 4 bytes, the write size of the flash.
+fn record_len(path_len: usize, data_len: usize) -> u32 {
+    (RECORD_HEADER_LEN + path_len + data_len).next_multiple_of(4) as u32
+}
+
+// CRC-32 (IEEE), bitwise as records are small.
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let crc = bytes.into_iter().fold(!0u32, |mut crc, byte| {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+        crc
+    });
+    !crc
+}
+
+/// A `MemFs` whose writes are also stored in a `Journal`, so the files survive a restart.
+///
+/// Writes only change the files in RAM, `commit` stores them in the journal. Appending can take a while
+/// when a block has to be collected, so it should not happen in the middle of an app update.
+///
+/// An empty file counts as removed, `read` returns `None` for it.
+pub struct JournalFs {
+    fs: MemFs,
+    journal: Option<Journal>,
+    // Files written since the last commit.
+    pending: Vec<String<MAX_PATH_LEN>, MAX_PENDING>,
+}
+
+impl JournalFs {
+    /// Files are only kept in RAM.
+    pub fn volatile() -> Self {
+        Self {
+            fs: MemFs::new(),
+            journal: None,
+            pending: Vec::new(),
+        }
+    }
+    /// Load the files stored on `device`, falls back to RAM only when it cannot be used.
+    pub fn mount(device: &'static mut dyn BlockDevice) -> Self {
+        let mut fs = MemFs::new();
+        let journal = Journal::mount(device, &mut fs)
+            .inspect_err(|err| error!("Failed to mount the journal: {:?}", err))
+            .ok();
+        Self {
+            fs,
+            journal,
+            pending: Vec::new(),
+        }
+    }
+    pub fn is_persistent(&self) -> bool {
+        self.journal.is_some()
+    }
+    /// See `Journal::usage`, `None` when the files are only kept in RAM.
+    pub fn usage(&self) -> Option<(u32, u32)> {
+        self.journal.as_ref().map(Journal::usage)
+    }
+    /// Remove every file.
+    pub fn format(&mut self) -> Result<(), JournalError> {
+        self.pending.clear();
+        if let Some(journal) = &mut self.journal {
+            journal.format()?;
+        }
+        self.fs = MemFs::new();
+        Ok(())
+    }
+    pub fn read(&self, path: &str) -> Option<&[u8]> {
+        self.fs.read(path).filter(|data| !data.is_empty())
+    }
+    /// Fails with `Pending` when `MAX_PENDING` other files were written since the last commit.
+    pub fn write(&mut self, path: &str, data: &[u8]) -> Result<(), JournalError> {
+        if self.journal.is_none() {
+            return self.fs.write(path, data).map_err(|_| JournalError::Fs);
+        }
+        if path.is_empty() || path.len() > MAX_PATH_LEN || data.len() > MAX_DATA_LEN {
+            return Err(JournalError::TooLarge);
+        }
+        // Committing here could collect a block in the middle of an app update.
+        if !self.pending.iter().any(|pending| pending == path) && self.pending.is_full() {
+            return Err(JournalError::Pending);
+        }
+        self.fs.write(path, data).map_err(|_| JournalError::Fs)?;
+        if !self.pending.iter().any(|pending| pending == path) {
+            let _ = self
+                .pending
+                .push(String::try_from(path).map_err(|_| JournalError::TooLarge)?);
+        }
+        Ok(())
+    }
+    /// Remove the file at `path`, it is removed from the journal on the next commit.
+    pub fn remove(&mut self, path: &str) -> Result<(), JournalError> {
+        if self.fs.read(path).is_none() {
+            return Ok(());
+        }
+        self.write(path, &[])
+    }
+    /// Store the files written since the last commit in the journal.
+    ///
+    /// Files that could not be stored are only kept in RAM, the first error is returned.
+    pub fn commit(&mut self) -> Result<(), JournalError> {
+        let Some(journal) = &mut self.journal else {
+            return Ok(());
+        };
+        let mut result = Ok(());
+        for path in core::mem::take(&mut self.pending) {
+            let Some(data) = self.fs.read(&path) else {
+                continue;
+            };
+            if let Err(err) = journal.append(&path, data, &self.fs) {
+                error!("Failed to store {}: {:?}", path.as_str(), err);
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{boxed::Box, cell::RefCell, rc::Rc};
+
+    type Ram = RamDevice<512, 4>;
+
+    // Shares the flash between mounts, and cuts the power after `budget` writes and erases.
+    // The write that is cut off only changes the first half of its bytes.
+    struct TestDevice {
+        ram: Rc<RefCell<Ram>>,
+        budget: Option<usize>,
+    }
+
+    impl TestDevice {
+        fn spend(&mut self) -> bool {
+            match &mut self.budget {
+                Some(0) => false,
+                Some(budget) => {
+                    *budget -= 1;
+                    true
+                }
+                None => true,
+            }
+        }
+    }
+
+    impl BlockDevice for TestDevice {
+        fn block_size(&self) -> u32 {
+            self.ram.borrow().block_size()
+        }
+        fn block_count(&self) -> u32 {
+            self.ram.borrow().block_count()
+        }
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+            self.ram.borrow_mut().read(offset, buf)
+        }
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlockError> {
+            if !self.spend() {
+                let _ = self.ram.borrow_mut().write(offset, &data[..data.len() / 2]);
+                return Err(BlockError::Device);
+            }
+            self.ram.borrow_mut().write(offset, data)
+        }
+        fn erase(&mut self, block: u32) -> Result<(), BlockError> {
+            if !self.spend() {
+                return Err(BlockError::Device);
+            }
+            self.ram.borrow_mut().erase(block)
+        }
+    }
+
+    fn device(ram: &Rc<RefCell<Ram>>, budget: Option<usize>) -> &'static mut TestDevice {
+        Box::leak(Box::new(TestDevice {
+            ram: ram.clone(),
+            budget,
+        }))
+    }
+
+    fn mount(ram: &Rc<RefCell<Ram>>, budget: Option<usize>) -> (Journal, MemFs) {
+        let mut fs = MemFs::new();
+        let journal = Journal::mount(device(ram, budget), &mut fs).unwrap();
+        (journal, fs)
+    }
+
+    fn write(journal: &mut Journal, fs: &mut MemFs, path: &str, data: &[u8]) -> bool {
+        let stored = journal.append(path, data, fs).is_ok();
+        if stored {
+            fs.write(path, data).unwrap();
+        }
+        stored
+    }
+
+    #[test]
+    fn files_survive_a_remount() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let (mut journal, mut fs) = mount(&ram, None);
+        assert!(write(&mut journal, &mut fs, "a", b"one"));
+        assert!(write(&mut journal, &mut fs, "b", b"two"));
+        assert!(write(&mut journal, &mut fs, "a", b"three"));
+
+        let (_, fs) = mount(&ram, None);
+        assert_eq!(fs.read("a"), Some(&b"three"[..]));
+        assert_eq!(fs.read("b"), Some(&b"two"[..]));
+        assert_eq!(fs.read("c"), None);
+    }
+
+    #[test]
+    fn torn_record_falls_back_to_the_previous_version() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        // Erasing the first block, its header and the first record.
+        let (mut journal, mut fs) = mount(&ram, Some(3));
+        assert!(write(&mut journal, &mut fs, "a", b"first"));
+        assert!(!write(&mut journal, &mut fs, "a", b"second"));
+
+        let (mut journal, mut fs) = mount(&ram, None);
+        assert_eq!(fs.read("a"), Some(&b"first"[..]));
+        // Nothing is written after the torn record, the next block is used instead.
+        assert!(write(&mut journal, &mut fs, "a", b"third"));
+        let (_, fs) = mount(&ram, None);
+        assert_eq!(fs.read("a"), Some(&b"third"[..]));
+    }
+
+    #[test]
+    fn collect_wraps_around_the_blocks() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let (mut journal, mut fs) = mount(&ram, None);
+        assert!(write(&mut journal, &mut fs, "kept", &[7; 100]));
+        // Many times the size of the device.
+        for idx in 0..200u8 {
+            let path = ["a", "b", "c"][idx as usize % 3];
+            assert!(write(&mut journal, &mut fs, path, &[idx; 100]));
+        }
+        let (used, size) = journal.usage();
+        assert!(used < size);
+
+        let (_, fs) = mount(&ram, None);
+        assert_eq!(fs.read("kept"), Some(&[7; 100][..]));
+        assert_eq!(fs.read("a"), Some(&[198; 100][..]));
+        assert_eq!(fs.read("b"), Some(&[199; 100][..]));
+        assert_eq!(fs.read("c"), Some(&[197; 100][..]));
+    }
+
+    #[test]
+    fn full_journal_is_reported() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let (mut journal, mut fs) = mount(&ram, None);
+        let mut stored = 0;
+        for idx in 0..20 {
+            let path = ["f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9"][idx % 10];
+            if write(&mut journal, &mut fs, path, &[1; MAX_DATA_LEN]) {
+                stored += 1;
+            }
+        }
+        assert!(stored < 20);
+        assert_eq!(
+            journal.append("f0", &[2; MAX_DATA_LEN], &fs),
+            Err(JournalError::Full)
+        );
+    }
+
+    #[test]
+    fn power_cut_at_any_point_keeps_the_files() {
+        // The files in the first block are copied when the fourth block is opened.
+        let mut writes: Vec<(&str, u8), 32> = Vec::new();
+        for (idx, path) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            writes.push((path, idx as u8)).unwrap();
+        }
+        for idx in 0..20 {
+            writes.push(("x", 10 + idx)).unwrap();
+        }
+
+        for budget in 0.. {
+            let ram = Rc::new(RefCell::new(Ram::new()));
+            let (mut journal, mut fs) = mount(&ram, Some(budget));
+            let Some(cut) = writes
+                .iter()
+                .position(|&(path, value)| !write(&mut journal, &mut fs, path, &[value; 100]))
+            else {
+                // The power was not cut anymore.
+                assert!(budget > 0);
+                break;
+            };
+
+            let (mut journal, mut fs) = mount(&ram, None);
+            for path in ["a", "b", "c", "d", "x"] {
+                let latest = writes[..cut].iter().rev().find(|(p, _)| *p == path);
+                let value = fs.read(path).map(|data| data[0]);
+                let (cut_path, cut_value) = writes[cut];
+                if path == cut_path && value == Some(cut_value) {
+                    continue;
+                }
+                assert_eq!(value, latest.map(|(_, value)| *value), "{budget} {path}");
+            }
+
+            // Appending works again, even when every block was in use.
+            for idx in 0..20 {
+                assert!(write(&mut journal, &mut fs, "y", &[idx; 100]), "{budget}");
+            }
+            let (_, fs) = mount(&ram, None);
+            assert_eq!(fs.read("y"), Some(&[19; 100][..]));
+            assert_eq!(fs.read("d").is_some(), cut > 3);
+        }
+    }
+
+    #[test]
+    fn writes_are_stored_on_commit() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let mut fs = JournalFs::mount(device(&ram, None));
+        fs.write("a", b"one").unwrap();
+        fs.write("a", b"two").unwrap();
+        assert_eq!(fs.read("a"), Some(&b"two"[..]));
+        assert_eq!(JournalFs::mount(device(&ram, None)).read("a"), None);
+
+        fs.commit().unwrap();
+        assert_eq!(
+            JournalFs::mount(device(&ram, None)).read("a"),
+            Some(&b"two"[..])
+        );
+        assert_eq!(
+            fs.write("b", &[0; MAX_DATA_LEN + 1]),
+            Err(JournalError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn writes_wait_for_a_commit_when_too_many_are_pending() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let mut fs = JournalFs::mount(device(&ram, None));
+        let mut path: String<MAX_PATH_LEN> = String::new();
+        for idx in 0..MAX_PENDING {
+            path.clear();
+            core::fmt::write(&mut path, format_args!("f{idx}")).unwrap();
+            fs.write(&path, b"data").unwrap();
+        }
+        // Files that are pending already can still be written.
+        fs.write("f0", b"new").unwrap();
+        assert_eq!(fs.write("g", b"data"), Err(JournalError::Pending));
+        assert_eq!(fs.read("g"), None);
+
+        fs.commit().unwrap();
+        fs.write("g", b"data").unwrap();
+    }
+
+    #[test]
+    fn removed_files_stay_removed() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let mut fs = JournalFs::mount(device(&ram, None));
+        fs.write("a", b"one").unwrap();
+        fs.write("b", b"two").unwrap();
+        fs.commit().unwrap();
+        fs.remove("a").unwrap();
+        assert_eq!(fs.read("a"), None);
+        fs.commit().unwrap();
+
+        let mut fs = JournalFs::mount(device(&ram, None));
+        assert_eq!(fs.read("a"), None);
+        assert_eq!(fs.read("b"), Some(&b"two"[..]));
+
+        // The removal is dropped once the blocks are collected, without bringing the file back.
+        for idx in 0..40u8 {
+            fs.write("b", &[idx; 100]).unwrap();
+            fs.commit().unwrap();
+        }
+        let fs = JournalFs::mount(device(&ram, None));
+        assert_eq!(fs.read("a"), None);
+        assert_eq!(fs.read("b"), Some(&[39; 100][..]));
+    }
+}
@@ -0,0 +1,454 @@
+use crate::{
+    graphics::*,
+    input::{ButtonId, ButtonManager, Rect, TooManyButtons},
+};
+
+pub const MAX_TEXT_LEN: usize = 38;
+
+pub type TextBuffer = heapless::String<MAX_TEXT_LEN>;
+
+// Grid rows used by the keyboard overlay.
+const PROMPT_ROW: u16 = 16;
+const TEXT_ROW: u16 = 17;
+const FIRST_KEY_ROW: u16 = 19;
+const KEY_ROW_HEIGHT: u16 = 3;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum KeyEvent {
+    Char(char),
+    Backspace,
+    Enter,
+    // Sent when the on-screen keyboard is closed with OK, holds the entered text.
+    Submit(TextBuffer),
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Layout {
+    Lower,
+    Upper,
+    Symbols,
+    Numeric,
+}
+
+#[derive(Clone, Copy)]
+enum KeyAction {
+    Char(char),
+    Shift,
+    Switch(Layout),
+    Backspace,
+    Enter,
+}
+
+#[derive(Clone, Copy)]
+struct Key {
+    id: ButtonId,
+    label: &'static str,
+    action: KeyAction,
+    // Width in grid cells, including the one cell gap to the next key.
+    width: u16,
+}
+
+// The button ids get a prefix, so a key cannot replace a button of the app with the same label.
+macro_rules! special {
+    ($label:literal, $action:expr, $width:expr) => {
+        Key {
+            id: concat!("KB_", $label),
+            label: $label,
+            action: $action,
+            width: $width,
+        }
+    };
+}
+
+macro_rules! ch {
+    ($label:literal, $c:literal) => {
+        special!($label, KeyAction::Char($c), 4)
+    };
+}
+
+const SHIFT: Key = special!("^", KeyAction::Shift, 4);
+const BACKSPACE: Key = special!("<-", KeyAction::Backspace, 4);
+const SPACE: Key = special!("SPACE", KeyAction::Char(' '), 16);
+const ENTER: Key = special!("OK", KeyAction::Enter, 4);
+const TO_LETTERS: Key = special!("abc", KeyAction::Switch(Layout::Lower), 4);
+const TO_SYMBOLS: Key = special!("#+=", KeyAction::Switch(Layout::Symbols), 4);
+const TO_NUMERIC: Key = special!("123", KeyAction::Switch(Layout::Numeric), 4);
+
+const LOWER: [&[Key]; 4] = [
+    &[
+        ch!("q", 'q'),
+        ch!("w", 'w'),
+        ch!("e", 'e'),
+        ch!("r", 'r'),
+        ch!("t", 't'),
+        ch!("y", 'y'),
+        ch!("u", 'u'),
+        ch!("i", 'i'),
+        ch!("o", 'o'),
+        ch!("p", 'p'),
+    ],
+    &[
+        ch!("a", 'a'),
+        ch!("s", 's'),
+        ch!("d", 'd'),
+        ch!("f", 'f'),
+        ch!("g", 'g'),
+        ch!("h", 'h'),
+        ch!("j", 'j'),
+        ch!("k", 'k'),
+        ch!("l", 'l'),
+    ],
+    &[
+        SHIFT,
+        ch!("z", 'z'),
+        ch!("x", 'x'),
+        ch!("c", 'c'),
+        ch!("v", 'v'),
+        ch!("b", 'b'),
+        ch!("n", 'n'),
+        ch!("m", 'm'),
+        BACKSPACE,
+    ],
+    &[TO_SYMBOLS, TO_NUMERIC, SPACE, ch!(".", '.'), ENTER],
+];
+
+const UPPER: [&[Key]; 4] = [
+    &[
+        ch!("Q", 'Q'),
+        ch!("W", 'W'),
+        ch!("E", 'E'),
+        ch!("R", 'R'),
+        ch!("T", 'T'),
+        ch!("Y", 'Y'),
+        ch!("U", 'U'),
+        ch!("I", 'I'),
+        ch!("O", 'O'),
+        ch!("P", 'P'),
+    ],
+    &[
+        ch!("A", 'A'),
+        ch!("S", 'S'),
+        ch!("D", 'D'),
+        ch!("F", 'F'),
+        ch!("G", 'G'),
+        ch!("H", 'H'),
+        ch!("J", 'J'),
+        ch!("K", 'K'),
+        ch!("L", 'L'),
+    ],
+    &[
+        SHIFT,
+        ch!("Z", 'Z'),
+        ch!("X", 'X'),
+        ch!("C", 'C'),
+        ch!("V", 'V'),
+        ch!("B", 'B'),
+        ch!("N", 'N'),
+        ch!("M", 'M'),
+        BACKSPACE,
+    ],
+    &[TO_SYMBOLS, TO_NUMERIC, SPACE, ch!(",", ','), ENTER],
+];
+
+const SYMBOLS: [&[Key]; 4] = [
+    &[
+        ch!("!", '!'),
+        ch!("@", '@'),
+        ch!("#", '#'),
+        ch!("$", '$'),
+        ch!("%", '%'),
+        ch!("^", '^'),
+        ch!("&", '&'),
+        ch!("*", '*'),
+        ch!("(", '('),
+        ch!(")", ')'),
+    ],
+    &[
+        ch!("-", '-'),
+        ch!("_", '_'),
+        ch!("=", '='),
+        ch!("+", '+'),
+        ch!("/", '/'),
+        ch!("\\", '\\'),
+        ch!(":", ':'),
+        ch!(";", ';'),
+        ch!("'", '\''),
+    ],
+    &[
+        ch!("\"", '"'),
+        ch!("<", '<'),
+        ch!(">", '>'),
+        ch!("[", '['),
+        ch!("]", ']'),
+        ch!("?", '?'),
+        ch!(",", ','),
+        ch!(".", '.'),
+        BACKSPACE,
+    ],
+    &[TO_LETTERS, TO_NUMERIC, SPACE, ch!("~", '~'), ENTER],
+];
+
+const NUMERIC: [&[Key]; 4] = [
+    &[
+        special!("1", KeyAction::Char('1'), 8),
+        special!("2", KeyAction::Char('2'), 8),
+        special!("3", KeyAction::Char('3'), 8),
+        special!("-", KeyAction::Char('-'), 8),
+    ],
+    &[
+        special!("4", KeyAction::Char('4'), 8),
+        special!("5", KeyAction::Char('5'), 8),
+        special!("6", KeyAction::Char('6'), 8),
+        special!("+", KeyAction::Char('+'), 8),
+    ],
+    &[
+        special!("7", KeyAction::Char('7'), 8),
+        special!("8", KeyAction::Char('8'), 8),
+        special!("9", KeyAction::Char('9'), 8),
+        special!("<-", KeyAction::Backspace, 8),
+    ],
+    &[
+        special!("abc", KeyAction::Switch(Layout::Lower), 8),
+        special!("0", KeyAction::Char('0'), 8),
+        special!(".", KeyAction::Char('.'), 8),
+        special!("OK", KeyAction::Enter, 8),
+    ],
+];
+
+/// System provided on-screen keyboard.
+///
+/// While open, the keys are registered as regular buttons so they are highlighted
+/// by `ButtonManager::draw_buttons`. Presses are turned into `KeyEvent`s for the active app.
+pub struct Keyboard {
+    open: bool,
+    prompt: &'static str,
+    text: TextBuffer,
+    layout: Layout,
+    // Shift only applies to the next character, unless it is pressed twice (caps lock).
+    caps_lock: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            prompt: "",
+            text: TextBuffer::new(),
+            layout: Layout::Lower,
+            caps_lock: false,
+        }
+    }
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// Fails when the buttons of the app leave no room for the keys of every layout.
+    pub fn open(
+        &mut self,
+        prompt: &'static str,
+        layout: Layout,
+        buttons: &mut ButtonManager,
+    ) -> Result<(), TooManyButtons> {
+        // The keys that are registered already are replaced.
+        let registered = if self.open { key_count(self.keys()) } else { 0 };
+        let most_keys = [&LOWER, &UPPER, &SYMBOLS, &NUMERIC]
+            .into_iter()
+            .map(key_count)
+            .max()
+            .unwrap_or_default();
+        if buttons.free_slots() + registered < most_keys {
+            return Err(TooManyButtons);
+        }
+        if self.open {
+            self.unregister_keys(buttons);
+        }
+        self.open = true;
+        self.prompt = prompt;
+        self.text.clear();
+        self.layout = layout;
+        self.caps_lock = false;
+        self.register_keys(buttons);
+        Ok(())
+    }
+    pub fn close(&mut self, buttons: &mut ButtonManager, grid: &mut ScreenGrid) {
+        if !self.open {
+            return;
+        }
+        self.unregister_keys(buttons);
+        self.open = false;
+        grid.draw_box(0, PROMPT_ROW, grid.cols, 31 - PROMPT_ROW, BASE03);
+    }
+    /// Returns true if the button belongs to the keyboard.
+    pub fn owns(&self, id: ButtonId) -> bool {
+        self.open && self.find(id).is_some()
+    }
+    /// Handle a released key, returns the `KeyEvent` for the app if any.
+    pub fn handle(
+        &mut self,
+        id: ButtonId,
+        buttons: &mut ButtonManager,
+        grid: &mut ScreenGrid,
+    ) -> Option<KeyEvent> {
+        let key = self.find(id)?;
+        match key.action {
+            KeyAction::Char(c) => {
+                if self.layout == Layout::Upper && !self.caps_lock {
+                    self.set_layout(Layout::Lower, buttons);
+                }
+                self.type_key(KeyEvent::Char(c), buttons, grid)
+            }
+            KeyAction::Backspace => self.type_key(KeyEvent::Backspace, buttons, grid),
+            KeyAction::Shift => {
+                match self.layout {
+                    Layout::Upper if !self.caps_lock => self.caps_lock = true,
+                    Layout::Upper => {
+                        self.caps_lock = false;
+                        self.set_layout(Layout::Lower, buttons);
+                    }
+                    _ => self.set_layout(Layout::Upper, buttons),
+                }
+                None
+            }
+            KeyAction::Switch(layout) => {
+                self.caps_lock = false;
+                self.set_layout(layout, buttons);
+                None
+            }
+            KeyAction::Enter => self.type_key(KeyEvent::Enter, buttons, grid),
+        }
+    }
+    /// Apply a key press from any source (touch, serial) to the text being edited.
+    pub fn type_key(
+        &mut self,
+        key: KeyEvent,
+        buttons: &mut ButtonManager,
+        grid: &mut ScreenGrid,
+    ) -> Option<KeyEvent> {
+        match key {
+            KeyEvent::Char(c) => {
+                if self.text.push(c).is_err() {
+                    return None;
+                }
+                Some(key)
+            }
+            KeyEvent::Backspace => {
+                self.text.pop();
+                Some(key)
+            }
+            KeyEvent::Enter => {
+                let text = self.text.clone();
+                self.close(buttons, grid);
+                Some(KeyEvent::Submit(text))
+            }
+            KeyEvent::Submit(_) => Some(key),
+        }
+    }
+    pub fn render(&self, grid: &mut ScreenGrid) {
+        if !self.open {
+            return;
+        }
+        grid.draw_box(0, PROMPT_ROW, grid.cols, 31 - PROMPT_ROW, BASE02);
+        grid.write_str(1, PROMPT_ROW, self.prompt, BASE1, BASE02);
+        grid.draw_box(1, TEXT_ROW, grid.cols - 2, 1, BASE03);
+        grid.write_str(1, TEXT_ROW, &self.text, BASE3, BASE03);
+        if self.caps_lock {
+            grid.write_str(grid.cols - 5, PROMPT_ROW, "CAPS", YELLOW, BASE02);
+        }
+    }
+
+    fn keys(&self) -> &'static [&'static [Key]; 4] {
+        match self.layout {
+            Layout::Lower => &LOWER,
+            Layout::Upper => &UPPER,
+            Layout::Symbols => &SYMBOLS,
+            Layout::Numeric => &NUMERIC,
+        }
+    }
+    fn find(&self, id: ButtonId) -> Option<Key> {
+        self.keys()
+            .iter()
+            .flat_map(|row| row.iter())
+            .find(|key| key.id == id)
+            .copied()
+    }
+    fn set_layout(&mut self, layout: Layout, buttons: &mut ButtonManager) {
+        self.unregister_keys(buttons);
+        self.layout = layout;
+        self.register_keys(buttons);
+    }
+    fn register_keys(&self, buttons: &mut ButtonManager) {
+        for (row_idx, row) in self.keys().iter().enumerate() {
+            let row_width: u16 = row.iter().map(|key| key.width).sum();
+            let mut col = (40 - row_width) / 2;
+            let y = FIRST_KEY_ROW + row_idx as u16 * KEY_ROW_HEIGHT;
+            for key in row.iter() {
+                // `open` made room for the largest layout.
+                let _ = buttons.register_labeled_button(
+                    key.id,
+                    key.label,
+                    Rect {
+                        x_min: col * CELL_W,
+                        y_min: y * CELL_H,
+                        x_max: (col + key.width - 1) * CELL_W,
+                        y_max: (y + KEY_ROW_HEIGHT - 1) * CELL_H,
+                    },
+                );
+                col += key.width;
+            }
+        }
+    }
+    fn unregister_keys(&self, buttons: &mut ButtonManager) {
+        for key in self.keys().iter().flat_map(|row| row.iter()) {
+            buttons.remove_button(key.id);
+        }
+    }
+}
+
+fn key_count(keys: &[&[Key]; 4]) -> usize {
+    keys.iter().map(|row| row.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::MAX_BUTTONS;
+
+    fn rect() -> Rect {
+        Rect {
+            x_min: 0,
+            y_min: 0,
+            x_max: 10,
+            y_max: 10,
+        }
+    }
+
+    #[test]
+    fn open_needs_room_for_every_layout() {
+        const IDS: [&str; 40] = [
+            "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15",
+            "16", "17", "18", "19", "20", "21", "22", "23", "24", "25", "26", "27", "28", "29",
+            "30", "31", "32", "33", "34", "35", "36", "37", "38", "39",
+        ];
+        let mut buttons = ButtonManager::new();
+        for id in IDS {
+            buttons.register_button(id, rect()).unwrap();
+        }
+        let mut keyboard = Keyboard::new();
+        assert_eq!(
+            keyboard.open("Name", Layout::Numeric, &mut buttons),
+            Err(TooManyButtons)
+        );
+        assert!(!keyboard.is_open());
+        assert_eq!(buttons.free_slots(), MAX_BUTTONS - IDS.len());
+
+        buttons.clear();
+        keyboard
+            .open("Name", Layout::Numeric, &mut buttons)
+            .unwrap();
+        // Switching to the largest layout fits as well.
+        keyboard.open("Name", Layout::Lower, &mut buttons).unwrap();
+        assert_eq!(buttons.free_slots(), MAX_BUTTONS - key_count(&LOWER));
+    }
+}
@@ -0,0 +1,108 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_hal::gpio::{Event, Input, InputConfig, Pull, WakeEvent};
+use esp_hal::time::Instant;
+
+use crate::events::{InputEvent, notify_input_irq, push_event};
+
+pub use crate::debounce::{Debouncer, HwKey, HwKeyEvent, PressKind};
+
+pub struct HardwareKeyPins {
+    pub boot: esp_hal::peripherals::GPIO0<'static>,
+    pub user: esp_hal::peripherals::GPIO21<'static>,
+}
+
+pub struct HardwareKeys<'a> {
+    boot: Input<'a>,
+    user: Input<'a>,
+    boot_state: Debouncer,
+    user_state: Debouncer,
+}
+
+impl<'a> HardwareKeys<'a> {
+    pub fn new(p: HardwareKeyPins) -> Self {
+        let config = InputConfig::default().with_pull(Pull::Up);
+        Self {
+            boot: Input::new(p.boot, config),
+            user: Input::new(p.user, config),
+            boot_state: Debouncer::new(),
+            user_state: Debouncer::new(),
+        }
+    }
+    /// Sample both keys and queue the resulting events. Returns true while a key is active.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now().duration_since_epoch().as_millis();
+
+        // Keys are active low.
+        if let Some(press) = self.boot_state.update(self.boot.is_low(), now) {
+            push_event(InputEvent::HwKey(HwKeyEvent {
+                key: HwKey::Boot,
+                press,
+            }));
+        }
+        if let Some(press) = self.user_state.update(self.user.is_low(), now) {
+            push_event(InputEvent::HwKey(HwKeyEvent {
+                key: HwKey::User,
+                press,
+            }));
+        }
+
+        self.boot_state.is_active() || self.user_state.is_active()
+    }
+}
+
+// Shared between the GPIO IRQ handler and the main loop.
+static KEYS: Mutex<RefCell<Option<HardwareKeys<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// Store the keys for polling and listen for presses, so a press wakes up the main loop.
+pub fn install_hardware_keys(mut keys: HardwareKeys<'static>) {
+    critical_section::with(|cs| {
+        keys.boot.listen(Event::FallingEdge);
+        keys.user.listen(Event::FallingEdge);
+        KEYS.borrow_ref_mut(cs).replace(keys);
+    });
+}
+
+/// Let a key press wake the CPU from light sleep, instead of raising the interrupt.
+pub fn set_wakeup(enable: bool) {
+    critical_section::with(|cs| {
+        if let Some(keys) = KEYS.borrow_ref_mut(cs).as_mut() {
+            for input in [&mut keys.boot, &mut keys.user] {
+                let _ = input.wakeup_enable(enable, WakeEvent::LowLevel);
+                if !enable {
+                    input.listen(Event::FallingEdge);
+                }
+            }
+        }
+    });
+}
+
+pub fn poll_hardware_keys() -> bool {
+    critical_section::with(|cs| {
+        KEYS.borrow_ref_mut(cs)
+            .as_mut()
+            .is_some_and(|keys| keys.poll())
+    })
+}
+
+/// Called from the GPIO interrupt handler. Debouncing is left to the polling in the input task.
+pub fn on_key_interrupt() {
+    critical_section::with(|cs| {
+        let mut keys = KEYS.borrow_ref_mut(cs);
+        let Some(keys) = keys.as_mut() else {
+            return;
+        };
+
+        let mut pressed = false;
+        for input in [&mut keys.boot, &mut keys.user] {
+            if input.is_interrupt_set() {
+                input.clear_interrupt();
+                pressed = true;
+            }
+        }
+        if pressed {
+            notify_input_irq();
+        }
+    });
+}
@@ -1,9 +1,54 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+// Some items of the pure modules are only used by the hardware modules.
+#![cfg_attr(not(target_arch = "xtensa"), allow(dead_code))]
+
+// The hardware modules only build for the device, the rest is also tested on the host.
+#[cfg(target_arch = "xtensa")]
+pub mod adc;
+#[cfg(target_arch = "xtensa")]
 pub mod apps;
+pub mod battery;
+pub mod calendar;
+pub mod calibration;
+#[cfg(target_arch = "xtensa")]
+pub mod clock;
+#[cfg(target_arch = "xtensa")]
+pub mod console;
+#[cfg(target_arch = "xtensa")]
+pub mod cpu;
+#[cfg(target_arch = "xtensa")]
+pub mod crash;
+pub mod debounce;
+#[cfg(target_arch = "xtensa")]
 pub mod display;
+#[cfg(target_arch = "xtensa")]
+pub mod events;
+#[cfg(target_arch = "xtensa")]
+pub mod flash;
 pub mod graphics;
 pub mod input;
+pub mod journal;
+pub mod keyboard;
+#[cfg(target_arch = "xtensa")]
+pub mod keys;
+#[cfg(target_arch = "xtensa")]
 pub mod log;
+#[cfg(test)]
+mod mock_time;
+#[cfg(target_arch = "xtensa")]
 pub mod power;
+pub mod power_state;
+#[cfg(target_arch = "xtensa")]
+pub mod scheduler;
+#[cfg(target_arch = "xtensa")]
+pub mod service;
+pub mod storage;
 pub mod system;
+#[cfg(target_arch = "xtensa")]
+pub mod timers;
+#[cfg(target_arch = "xtensa")]
+pub mod toast;
+#[cfg(target_arch = "xtensa")]
 pub mod touch;
+#[cfg(target_arch = "xtensa")]
+pub mod watchdog;
@@ -1,5 +1,5 @@
 use esp_println::println;
-use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
+use log::{LevelFilter, Metadata, Record, SetLoggerError};
 
 static LOGGER: Logger = Logger;
 
@@ -7,7 +7,8 @@ struct Logger;
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        // The level can be changed at runtime with `SystemCmd::SetLogLevel`.
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
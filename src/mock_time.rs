@@ -0,0 +1,27 @@
+use std::sync::Mutex;
+
+use embassy_futures::{select::select, yield_now};
+use embassy_time::{Duration, MockDriver};
+
+// All tests share the mock time driver, the ones that use it run one at a time.
+static TIME: Mutex<()> = Mutex::new(());
+
+/// Run `task` on the std executor until `script` is done, starting at time 0.
+///
+/// The task is polled first, so it runs up to its first wait before the script starts.
+pub fn run_task(task: impl Future, script: impl Future<Output = ()>) {
+    let _time = TIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    MockDriver::get().reset();
+    futures_executor::block_on(select(task, script));
+}
+
+/// Let `ms` milliseconds pass, then let the task handle its timers.
+pub async fn advance(ms: u64) {
+    MockDriver::get().advance(Duration::from_millis(ms));
+    settle().await;
+}
+
+/// Let the task handle what woke it, before the script continues.
+pub async fn settle() {
+    yield_now().await;
+}
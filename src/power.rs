@@ -1,79 +1,123 @@
+use crate::clock;
+use crate::cpu;
+use crate::power_state::{PowerControl, PowerManager, PowerSignals, WakeLocks};
+use crate::service;
 use crate::system::{SystemCmd, SystemSettings};
-use core::cell::RefCell;
-use esp_hal::{
-    delay::Delay,
-    time::{Duration, Instant},
-};
+use core::cell::{Cell, RefCell};
+use critical_section::Mutex;
+use esp_hal::time::{Duration, Instant};
 use log::info;
 
-#[derive(PartialEq, Clone, Copy, Debug)]
-pub enum PowerMode {
-    Active, // High refresh rate
-    Idle,   // Low refresh rate, dim screen.
-    Sleep,  // Lower refresh rate, screen off.
+pub use crate::power_state::{PowerMode, PowerProfile, WakeLockKind};
+
+// Longest light sleep, so the tasks that poll keep running now and then.
+const MAX_LIGHT_SLEEP: Duration = Duration::from_secs(30);
+
+static SIGNALS: PowerSignals = PowerSignals::new();
+
+static MODE: Mutex<Cell<PowerMode>> = Mutex::new(Cell::new(PowerMode::Active));
+
+// `clock::monotonic_ms` of the next mode change.
+static NEXT_TRANSITION: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+static LOCKS: Mutex<Cell<WakeLocks>> = Mutex::new(Cell::new(WakeLocks { screen: 0, cpu: 0 }));
+
+pub fn report_activity() {
+    SIGNALS.activity.signal(());
 }
 
-pub struct PowerManager {
-    mode: PowerMode,
-    last_activity: Instant,
-    delay: Delay,
+/// Let the power task check the timeouts, after the CPU was in light sleep.
+pub fn recheck() {
+    SIGNALS.recheck.signal(());
 }
 
-impl PowerManager {
-    pub fn new() -> Self {
-        Self {
-            mode: PowerMode::Active,
-            last_activity: Instant::now(),
-            delay: Delay::new(),
-        }
+/// The current power mode, as last set by the power task.
+pub fn power_mode() -> PowerMode {
+    critical_section::with(|cs| MODE.borrow(cs).get())
+}
+
+/// Keeps the screen or the CPU on until it is dropped, see `WakeLockKind`.
+///
+/// Apps keep it in their state, so it is released when the app closes.
+pub struct WakeLock {
+    kind: WakeLockKind,
+    owner: &'static str,
+}
+
+impl Drop for WakeLock {
+    fn drop(&mut self) {
+        update_locks(|locks| locks.release(self.kind));
+        info!("Wake lock {:?} released by {}", self.kind, self.owner);
+        // The timeouts start over, so the screen does not go dark right away.
+        report_activity();
     }
-    pub fn update(&mut self, settings: &RefCell<SystemSettings>) -> Option<SystemCmd> {
-        let mut s = settings.borrow_mut();
-        let elapsed = self.last_activity.elapsed();
-
-        let new_mode = if elapsed > Duration::from_secs(s.sleep_time) {
-            PowerMode::Sleep
-        } else if elapsed > Duration::from_secs(s.idle_time) {
-            PowerMode::Idle
-        } else {
-            PowerMode::Active
-        };
+}
 
-        let effective = match new_mode {
-            PowerMode::Active => s.user_brightness,
-            PowerMode::Idle => s.user_brightness / 2,
-            PowerMode::Sleep => 0,
-        };
+/// Take a wake lock for `owner`, usually the name of the app.
+pub fn wake_lock(kind: WakeLockKind, owner: &'static str) -> WakeLock {
+    update_locks(|locks| locks.acquire(kind));
+    info!("Wake lock {:?} taken by {}", kind, owner);
+    recheck();
+    WakeLock { kind, owner }
+}
 
-        let changed = (new_mode != self.mode) || (effective != s.effective_brightness);
+/// The wake locks held at the moment.
+pub fn wake_locks() -> WakeLocks {
+    critical_section::with(|cs| LOCKS.borrow(cs).get())
+}
 
-        self.mode = new_mode;
-        s.effective_brightness = effective;
+fn update_locks(f: impl FnOnce(&mut WakeLocks)) {
+    critical_section::with(|cs| {
+        let mut locks = LOCKS.borrow(cs).get();
+        f(&mut locks);
+        LOCKS.borrow(cs).set(locks);
+    });
+}
 
-        if changed {
-            info!("Power Mode: {:?}", self.mode);
-            Some(SystemCmd::SetBrightness(effective))
+/// How long the CPU may stay in light sleep, until `deadline` or the next mode change.
+pub fn light_sleep_duration(deadline: Option<Instant>) -> Duration {
+    let now = Instant::now();
+    let frame = deadline.map(|deadline| {
+        if deadline > now {
+            deadline - now
         } else {
-            None
+            Duration::ZERO
         }
+    });
+    let now_ms = clock::monotonic_ms();
+    let transition = critical_section::with(|cs| NEXT_TRANSITION.borrow(cs).get())
+        .map(|at| Duration::from_millis(at.saturating_sub(now_ms)));
+    [frame, transition]
+        .into_iter()
+        .flatten()
+        .fold(MAX_LIGHT_SLEEP, Duration::min)
+}
+
+// The power task on the device.
+struct Device;
+
+impl PowerControl for Device {
+    fn now_ms(&self) -> u64 {
+        clock::monotonic_ms()
     }
-    pub fn get_power_mode(&self) -> PowerMode {
-        self.mode
+    fn wake_locks(&self) -> WakeLocks {
+        wake_locks()
     }
-    pub fn register_activity(&mut self) {
-        self.last_activity = Instant::now();
+    fn set_mode(&self, mode: PowerMode, profile: PowerProfile, next_transition: Option<u64>) {
+        critical_section::with(|cs| {
+            MODE.borrow(cs).set(mode);
+            NEXT_TRANSITION.borrow(cs).set(next_transition);
+        });
+        cpu::set_power_mode(mode, profile);
     }
-    pub fn await_frame(&mut self) {
-        match self.mode {
-            PowerMode::Active => {
-                self.delay.delay_millis(16);
-            }
-            PowerMode::Idle => {
-                self.delay.delay_millis(200);
-            }
-            PowerMode::Sleep => {
-                self.delay.delay_millis(400);
-            }
-        }
+    fn send(&self, cmd: SystemCmd) {
+        service::send(cmd);
     }
 }
+
+/// Power task body, see `PowerManager::run`.
+pub async fn run(settings: &RefCell<SystemSettings>) -> ! {
+    PowerManager::new(clock::monotonic_ms())
+        .run(settings, &Device, &SIGNALS)
+        .await
+}
@@ -0,0 +1,527 @@
+use core::cell::RefCell;
+
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Timer;
+use log::info;
+
+use crate::system::{SystemCmd, SystemSettings};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PowerMode {
+    Active, // High refresh rate
+    Idle,   // Low refresh rate, dim screen.
+    Sleep,  // Screen off, light sleep between frames.
+    Off,    // Deep sleep, a touch restarts the firmware.
+}
+
+/// Time without activity before each mode, in milliseconds.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct PowerTimeouts {
+    pub idle_ms: u64,
+    pub sleep_ms: u64,
+    // `None` to never power off.
+    pub off_ms: Option<u64>,
+    // Stay in idle instead of sleeping.
+    pub never_sleep: bool,
+    // A wake lock keeps the screen on, in the active mode.
+    pub screen_locked: bool,
+    // A wake lock keeps the CPU running, the screen may still turn off.
+    pub cpu_locked: bool,
+}
+
+impl PowerTimeouts {
+    // The mode after `elapsed` ms without activity.
+    fn mode_after(&self, elapsed: u64) -> PowerMode {
+        if self.screen_locked {
+            return PowerMode::Active;
+        }
+        let off = self.off_ms.is_some_and(|off| elapsed > off) && !self.cpu_locked;
+        if off && !self.never_sleep {
+            PowerMode::Off
+        } else if elapsed > self.sleep_ms && !self.never_sleep {
+            PowerMode::Sleep
+        } else if elapsed > self.idle_ms {
+            PowerMode::Idle
+        } else {
+            PowerMode::Active
+        }
+    }
+    // Timeout of the mode after `mode`, if there is one.
+    fn timeout_after(&self, mode: PowerMode) -> Option<u64> {
+        match mode {
+            PowerMode::Active if self.screen_locked => None,
+            PowerMode::Active => Some(self.idle_ms),
+            PowerMode::Idle if self.never_sleep => None,
+            PowerMode::Idle => Some(self.sleep_ms),
+            PowerMode::Sleep if self.cpu_locked => None,
+            PowerMode::Sleep => self.off_ms,
+            PowerMode::Off => None,
+        }
+    }
+}
+
+/// Power mode transitions, driven by activity and the time that passed since.
+///
+/// Times are milliseconds of a clock that keeps running in light sleep.
+pub struct PowerStateMachine {
+    mode: PowerMode,
+    last_activity_ms: u64,
+}
+
+impl PowerStateMachine {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            mode: PowerMode::Active,
+            last_activity_ms: now_ms,
+        }
+    }
+    pub fn mode(&self) -> PowerMode {
+        self.mode
+    }
+    /// Restart the timeouts, the next `update` returns to the active mode.
+    pub fn activity(&mut self, now_ms: u64) {
+        self.last_activity_ms = now_ms;
+    }
+    /// Move to the mode for the time since the last activity, returns the new mode when it changed.
+    pub fn update(&mut self, now_ms: u64, timeouts: &PowerTimeouts) -> Option<PowerMode> {
+        let mode = timeouts.mode_after(now_ms.saturating_sub(self.last_activity_ms));
+        if mode == self.mode {
+            return None;
+        }
+        self.mode = mode;
+        Some(mode)
+    }
+    /// When the mode changes next without new activity.
+    pub fn next_transition(&self, timeouts: &PowerTimeouts) -> Option<u64> {
+        // The mode changes once the timeout has passed, not when it is reached.
+        timeouts
+            .timeout_after(self.mode)
+            .map(|timeout| self.last_activity_ms + timeout + 1)
+    }
+}
+
+/// CPU clock, all three are taken from the same PLL so switching between them is quick.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CpuSpeed {
+    Low,    // 80 MHz
+    Medium, // 160 MHz
+    High,   // 240 MHz
+}
+
+impl CpuSpeed {
+    pub fn mhz(self) -> u32 {
+        match self {
+            CpuSpeed::Low => 80,
+            CpuSpeed::Medium => 160,
+            CpuSpeed::High => 240,
+        }
+    }
+}
+
+/// Trades responsiveness for battery life.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PowerProfile {
+    // Full clock until the screen turns off.
+    Performance,
+    Balanced,
+    // Lower clocks, and the timeouts are halved.
+    Saver,
+}
+
+impl PowerProfile {
+    // In the order of their stored value.
+    pub const ALL: [PowerProfile; 3] = [
+        PowerProfile::Performance,
+        PowerProfile::Balanced,
+        PowerProfile::Saver,
+    ];
+
+    pub fn next(self) -> Self {
+        match self {
+            PowerProfile::Performance => PowerProfile::Balanced,
+            PowerProfile::Balanced => PowerProfile::Saver,
+            PowerProfile::Saver => PowerProfile::Performance,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            PowerProfile::Performance => "Performance",
+            PowerProfile::Balanced => "Balanced",
+            PowerProfile::Saver => "Saver",
+        }
+    }
+    /// The CPU clock in a power mode, when no app asked for full speed.
+    pub fn cpu_speed(self, mode: PowerMode) -> CpuSpeed {
+        match (self, mode) {
+            (_, PowerMode::Sleep | PowerMode::Off) => CpuSpeed::Low,
+            (PowerProfile::Performance, _) => CpuSpeed::High,
+            (PowerProfile::Balanced, PowerMode::Active) => CpuSpeed::High,
+            (PowerProfile::Balanced, PowerMode::Idle) => CpuSpeed::Medium,
+            (PowerProfile::Saver, PowerMode::Active) => CpuSpeed::Medium,
+            (PowerProfile::Saver, PowerMode::Idle) => CpuSpeed::Low,
+        }
+    }
+    /// A timeout of the settings in milliseconds.
+    pub fn timeout_ms(self, secs: u64) -> u64 {
+        match self {
+            PowerProfile::Saver => secs * 500,
+            _ => secs * 1000,
+        }
+    }
+}
+
+/// What a wake lock keeps running.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WakeLockKind {
+    // The screen stays on at full brightness.
+    Screen,
+    // The CPU does not sleep or power off, the screen may still turn off.
+    Cpu,
+}
+
+/// Number of wake locks held of each kind.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct WakeLocks {
+    pub screen: u8,
+    pub cpu: u8,
+}
+
+impl WakeLocks {
+    fn count_mut(&mut self, kind: WakeLockKind) -> &mut u8 {
+        match kind {
+            WakeLockKind::Screen => &mut self.screen,
+            WakeLockKind::Cpu => &mut self.cpu,
+        }
+    }
+    pub fn acquire(&mut self, kind: WakeLockKind) {
+        let count = self.count_mut(kind);
+        *count = count.saturating_add(1);
+    }
+    pub fn release(&mut self, kind: WakeLockKind) {
+        let count = self.count_mut(kind);
+        *count = count.saturating_sub(1);
+    }
+}
+
+/// What the power task reads and controls, the device uses the RTC, the CPU clock and the system service.
+pub trait PowerControl {
+    /// Milliseconds on a clock that keeps counting in light sleep.
+    fn now_ms(&self) -> u64;
+    fn wake_locks(&self) -> WakeLocks;
+    /// Publish the mode and when it changes next, and set the CPU clock for the mode.
+    fn set_mode(&self, mode: PowerMode, profile: PowerProfile, next_transition: Option<u64>);
+    /// Hand a command to the UI task, the display belongs to it.
+    fn send(&self, cmd: SystemCmd);
+}
+
+/// Wake the power task before its timeout.
+pub struct PowerSignals {
+    // Input or other user activity, resets the idle timeout.
+    pub activity: Signal<CriticalSectionRawMutex, ()>,
+    // The timer of the power task does not count while the CPU is in light sleep,
+    // so the UI task asks it to check the timeouts again after waking up.
+    pub recheck: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl PowerSignals {
+    pub const fn new() -> Self {
+        Self {
+            activity: Signal::new(),
+            recheck: Signal::new(),
+        }
+    }
+}
+
+fn timeouts(s: &SystemSettings, locks: WakeLocks) -> PowerTimeouts {
+    PowerTimeouts {
+        idle_ms: s.power_profile.timeout_ms(s.idle_time),
+        sleep_ms: s.power_profile.timeout_ms(s.sleep_time),
+        off_ms: (s.off_time > 0).then_some(s.power_profile.timeout_ms(s.off_time)),
+        never_sleep: s.never_sleep,
+        screen_locked: locks.screen > 0,
+        cpu_locked: locks.cpu > 0,
+    }
+}
+
+pub struct PowerManager {
+    machine: PowerStateMachine,
+}
+
+impl PowerManager {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            machine: PowerStateMachine::new(now_ms),
+        }
+    }
+    /// Switch the mode for the time since the last activity, returns the commands for the display.
+    pub fn update(
+        &mut self,
+        now_ms: u64,
+        locks: WakeLocks,
+        settings: &RefCell<SystemSettings>,
+    ) -> heapless::Vec<SystemCmd, 3> {
+        let mut s = settings.borrow_mut();
+        let previous = self.machine.mode();
+        let changed = self.machine.update(now_ms, &timeouts(&s, locks)).is_some();
+        let mode = self.machine.mode();
+        if changed {
+            info!("Power Mode: {:?}", mode);
+        }
+
+        let effective = match mode {
+            PowerMode::Active => s.user_brightness,
+            PowerMode::Idle => (s.user_brightness as u16 * s.idle_brightness as u16 / 100) as u8,
+            PowerMode::Sleep | PowerMode::Off => 0,
+        };
+        let backlight = changed || effective != s.effective_brightness;
+        s.effective_brightness = effective;
+
+        let mut cmds = heapless::Vec::new();
+        let asleep = |mode| matches!(mode, PowerMode::Sleep | PowerMode::Off);
+        // The controller is woken before the backlight turns on, and put to sleep after it turned off.
+        if asleep(previous) && !asleep(mode) {
+            let _ = cmds.push(SystemCmd::SetDisplaySleep(false));
+        }
+        if backlight {
+            let _ = cmds.push(SystemCmd::SetBacklight(effective));
+        }
+        if !asleep(previous) && asleep(mode) {
+            let _ = cmds.push(SystemCmd::SetDisplaySleep(true));
+        }
+        if changed && mode == PowerMode::Off {
+            let _ = cmds.push(SystemCmd::PowerOff);
+        }
+        cmds
+    }
+    pub fn mode(&self) -> PowerMode {
+        self.machine.mode()
+    }
+    /// Power task body, switches the power mode when there was no activity for a while.
+    pub async fn run(
+        &mut self,
+        settings: &RefCell<SystemSettings>,
+        control: &impl PowerControl,
+        signals: &PowerSignals,
+    ) -> ! {
+        loop {
+            let cmds = self.update(control.now_ms(), control.wake_locks(), settings);
+            let next = self
+                .machine
+                .next_transition(&timeouts(&settings.borrow(), control.wake_locks()));
+            control.set_mode(self.machine.mode(), settings.borrow().power_profile, next);
+            for cmd in cmds {
+                control.send(cmd);
+            }
+
+            let activity = match next {
+                Some(at) => {
+                    let timeout = at.saturating_sub(control.now_ms());
+                    let wait = select3(
+                        signals.activity.wait(),
+                        signals.recheck.wait(),
+                        Timer::after_millis(timeout),
+                    );
+                    matches!(wait.await, Either3::First(()))
+                }
+                None => matches!(
+                    select(signals.activity.wait(), signals.recheck.wait()).await,
+                    Either::First(())
+                ),
+            };
+            if activity {
+                self.machine.activity(control.now_ms());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_time::{advance, run_task, settle};
+    use core::cell::Cell;
+
+    const TIMEOUTS: PowerTimeouts = PowerTimeouts {
+        idle_ms: 10_000,
+        sleep_ms: 60_000,
+        off_ms: Some(300_000),
+        never_sleep: false,
+        screen_locked: false,
+        cpu_locked: false,
+    };
+
+    #[test]
+    fn modes_follow_in_order() {
+        let mut state = PowerStateMachine::new(1000);
+        assert_eq!(state.update(11_000, &TIMEOUTS), None);
+        assert_eq!(state.update(11_001, &TIMEOUTS), Some(PowerMode::Idle));
+        assert_eq!(state.update(61_000, &TIMEOUTS), None);
+        assert_eq!(state.update(61_001, &TIMEOUTS), Some(PowerMode::Sleep));
+        assert_eq!(state.update(301_001, &TIMEOUTS), Some(PowerMode::Off));
+
+        state.activity(400_000);
+        assert_eq!(state.update(400_000, &TIMEOUTS), Some(PowerMode::Active));
+        // A long gap, like a light sleep, skips the modes in between.
+        assert_eq!(state.update(800_000, &TIMEOUTS), Some(PowerMode::Off));
+    }
+
+    #[test]
+    fn never_sleep_stays_idle() {
+        let timeouts = PowerTimeouts {
+            never_sleep: true,
+            ..TIMEOUTS
+        };
+        let mut state = PowerStateMachine::new(0);
+        assert_eq!(state.update(1_000_000, &timeouts), Some(PowerMode::Idle));
+        assert_eq!(state.next_transition(&timeouts), None);
+    }
+
+    #[test]
+    fn without_power_off_the_device_sleeps() {
+        let timeouts = PowerTimeouts {
+            off_ms: None,
+            ..TIMEOUTS
+        };
+        let mut state = PowerStateMachine::new(0);
+        assert_eq!(state.update(1_000_000, &timeouts), Some(PowerMode::Sleep));
+        assert_eq!(state.next_transition(&timeouts), None);
+    }
+
+    #[test]
+    fn screen_lock_stays_active() {
+        let timeouts = PowerTimeouts {
+            screen_locked: true,
+            ..TIMEOUTS
+        };
+        let mut state = PowerStateMachine::new(0);
+        assert_eq!(state.update(1_000_000, &timeouts), None);
+        assert_eq!(state.mode(), PowerMode::Active);
+        assert_eq!(state.next_transition(&timeouts), None);
+
+        // Releasing the lock reports activity, so the timeouts start over.
+        state.activity(1_000_000);
+        assert_eq!(state.update(1_000_000, &TIMEOUTS), None);
+        assert_eq!(state.mode(), PowerMode::Active);
+        assert_eq!(state.next_transition(&TIMEOUTS), Some(1_010_001));
+    }
+
+    #[test]
+    fn cpu_lock_prevents_power_off() {
+        let timeouts = PowerTimeouts {
+            cpu_locked: true,
+            ..TIMEOUTS
+        };
+        let mut state = PowerStateMachine::new(0);
+        assert_eq!(state.update(1_000_000, &timeouts), Some(PowerMode::Sleep));
+        assert_eq!(state.next_transition(&timeouts), None);
+    }
+
+    #[test]
+    fn next_transition_changes_the_mode() {
+        let mut state = PowerStateMachine::new(1000);
+        let mut modes = [PowerMode::Active; 3];
+        for mode in &mut modes {
+            let at = state.next_transition(&TIMEOUTS).unwrap();
+            assert_eq!(state.update(at - 1, &TIMEOUTS), None);
+            *mode = state.update(at, &TIMEOUTS).unwrap();
+        }
+        assert_eq!(modes, [PowerMode::Idle, PowerMode::Sleep, PowerMode::Off]);
+        assert_eq!(state.next_transition(&TIMEOUTS), None);
+    }
+
+    #[test]
+    fn wake_locks_are_counted() {
+        let mut locks = WakeLocks::default();
+        locks.acquire(WakeLockKind::Cpu);
+        locks.acquire(WakeLockKind::Cpu);
+        locks.release(WakeLockKind::Cpu);
+        assert_eq!(locks, WakeLocks { screen: 0, cpu: 1 });
+        locks.release(WakeLockKind::Screen);
+        assert_eq!(locks.screen, 0);
+    }
+
+    // Records what the power task did, on the time of the mock driver.
+    struct TestControl {
+        locks: Cell<WakeLocks>,
+        mode: Cell<Option<PowerMode>>,
+        cmds: RefCell<Vec<SystemCmd>>,
+    }
+
+    impl TestControl {
+        fn new(locks: WakeLocks) -> Self {
+            Self {
+                locks: Cell::new(locks),
+                mode: Cell::new(None),
+                cmds: RefCell::new(Vec::new()),
+            }
+        }
+        fn take_cmds(&self) -> Vec<SystemCmd> {
+            self.cmds.take()
+        }
+    }
+
+    impl PowerControl for TestControl {
+        fn now_ms(&self) -> u64 {
+            embassy_time::Instant::now().as_millis()
+        }
+        fn wake_locks(&self) -> WakeLocks {
+            self.locks.get()
+        }
+        fn set_mode(&self, mode: PowerMode, _profile: PowerProfile, _next: Option<u64>) {
+            self.mode.set(Some(mode));
+        }
+        fn send(&self, cmd: SystemCmd) {
+            self.cmds.borrow_mut().push(cmd);
+        }
+    }
+
+    #[test]
+    fn task_dims_sleeps_and_wakes() {
+        // Idle after 10 s and sleep after 60 s, without powering off.
+        let settings = RefCell::new(SystemSettings::default());
+        let control = TestControl::new(WakeLocks { screen: 0, cpu: 0 });
+        let signals = PowerSignals::new();
+        let mut manager = PowerManager::new(0);
+        run_task(manager.run(&settings, &control, &signals), async {
+            assert_eq!(control.mode.get(), Some(PowerMode::Active));
+            advance(10_000).await;
+            assert_eq!(control.mode.get(), Some(PowerMode::Active));
+            advance(1).await;
+            assert_eq!(control.mode.get(), Some(PowerMode::Idle));
+            assert_eq!(control.take_cmds(), [SystemCmd::SetBacklight(50)]);
+
+            advance(50_000).await;
+            assert_eq!(control.mode.get(), Some(PowerMode::Sleep));
+            let sleep = [SystemCmd::SetBacklight(0), SystemCmd::SetDisplaySleep(true)];
+            assert_eq!(control.take_cmds(), sleep);
+
+            signals.activity.signal(());
+            settle().await;
+            assert_eq!(control.mode.get(), Some(PowerMode::Active));
+            let wake = [
+                SystemCmd::SetDisplaySleep(false),
+                SystemCmd::SetBacklight(100),
+            ];
+            assert_eq!(control.take_cmds(), wake);
+        });
+    }
+
+    #[test]
+    fn task_rechecks_the_wake_locks() {
+        let settings = RefCell::new(SystemSettings::default());
+        let control = TestControl::new(WakeLocks { screen: 1, cpu: 0 });
+        let signals = PowerSignals::new();
+        let mut manager = PowerManager::new(0);
+        run_task(manager.run(&settings, &control, &signals), async {
+            advance(100_000).await;
+            assert_eq!(control.mode.get(), Some(PowerMode::Active));
+
+            // Without the lock the timeouts count from the last activity.
+            control.locks.set(WakeLocks { screen: 0, cpu: 0 });
+            signals.recheck.signal(());
+            settle().await;
+            assert_eq!(control.mode.get(), Some(PowerMode::Sleep));
+        });
+    }
+}
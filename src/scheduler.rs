@@ -0,0 +1,122 @@
+use embassy_futures::select::select;
+use embassy_time::Timer;
+use esp_hal::time::{Duration, Instant};
+use log::info;
+
+use crate::events::wait_for_wake;
+
+// Target frame time, frames that take longer are counted as over budget.
+pub const FRAME_BUDGET: Duration = Duration::from_millis(16);
+
+// Frame statistics are logged and reset this often.
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time left until `deadline`, as an embassy duration for `Timer::after`.
+pub fn time_until(deadline: Instant) -> embassy_time::Duration {
+    let now = Instant::now();
+    if deadline <= now {
+        return embassy_time::Duration::from_ticks(0);
+    }
+    embassy_time::Duration::from_micros((deadline - now).as_micros())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats {
+    pub frames: u32,
+    // Frames that took longer than `FRAME_BUDGET` to process.
+    pub over_budget: u32,
+    pub max_work_ms: u64,
+    pub total_work_ms: u64,
+    pub total_sleep_ms: u64,
+}
+
+impl FrameStats {
+    pub const fn new() -> Self {
+        Self {
+            frames: 0,
+            over_budget: 0,
+            max_work_ms: 0,
+            total_work_ms: 0,
+            total_sleep_ms: 0,
+        }
+    }
+    pub fn record(&mut self, work_ms: u64, sleep_ms: u64) {
+        self.frames += 1;
+        if work_ms > FRAME_BUDGET.as_millis() {
+            self.over_budget += 1;
+        }
+        self.max_work_ms = self.max_work_ms.max(work_ms);
+        self.total_work_ms += work_ms;
+        self.total_sleep_ms += sleep_ms;
+    }
+    /// Share of the time spent processing frames instead of sleeping.
+    pub fn busy_percent(&self) -> u64 {
+        let total = self.total_work_ms + self.total_sleep_ms;
+        if total == 0 {
+            return 0;
+        }
+        self.total_work_ms * 100 / total
+    }
+}
+
+/// Decides when the main loop runs the next frame.
+///
+/// During a frame, everything that needs the loop to run again adds a deadline.
+/// `await_next_frame` then sleeps until the earliest one, or until an event is queued.
+pub struct FrameScheduler {
+    frame_start: Instant,
+    next_wake: Option<Instant>,
+    stats: FrameStats,
+    stats_start: Instant,
+}
+
+impl FrameScheduler {
+    pub fn new() -> Self {
+        Self {
+            frame_start: Instant::now(),
+            next_wake: None,
+            stats: FrameStats::new(),
+            stats_start: Instant::now(),
+        }
+    }
+    pub fn begin_frame(&mut self) {
+        self.frame_start = Instant::now();
+        self.next_wake = None;
+    }
+    /// Run the next frame no later than `deadline`.
+    pub fn wake_at(&mut self, deadline: Instant) {
+        self.next_wake = Some(match self.next_wake {
+            Some(current) => current.min(deadline),
+            None => deadline,
+        });
+    }
+    /// The earliest deadline of this frame so far.
+    pub fn next_wake(&self) -> Option<Instant> {
+        self.next_wake
+    }
+    /// Sleep until the earliest deadline of this frame, or until woken by `request_wake`.
+    pub async fn await_next_frame(&mut self) {
+        let work_end = Instant::now();
+        let work_ms = (work_end - self.frame_start).as_millis();
+
+        match self.next_wake {
+            Some(deadline) => {
+                select(wait_for_wake(), Timer::after(time_until(deadline))).await;
+            }
+            None => wait_for_wake().await,
+        }
+
+        self.stats.record(work_ms, work_end.elapsed().as_millis());
+        if self.stats_start.elapsed() >= STATS_INTERVAL {
+            info!(
+                "Frames: {}, over budget: {}, max: {}ms, busy: {}%",
+                self.stats.frames,
+                self.stats.over_budget,
+                self.stats.max_work_ms,
+                self.stats.busy_percent()
+            );
+            self.stats = FrameStats::new();
+            self.stats_start = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use core::cell::RefCell;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use esp_hal::{
+    peripherals::TIMG1,
+    rtc_cntl::sleep::{GpioWakeupSource, TimerWakeupSource},
+    time::{Duration, Instant},
+    timer::timg::{MwdtStage, Wdt},
+};
+use heapless::String;
+use log::{error, info, warn};
+
+use crate::{
+    apps::{
+        app::{App, AppResponse, Context},
+        manager::AppManager,
+    },
+    clock, cpu,
+    display::{DisplayDriver, ScreenRotation},
+    events::{notify_input_irq, request_wake},
+    graphics::{BASE03, ScreenGrid},
+    keyboard::Keyboard,
+    keys,
+    power::{recheck, report_activity},
+    system::{SYSTEM_NAMESPACE, SystemCmd, SystemError, SystemResult, SystemSettings},
+    toast::Toast,
+    touch,
+    watchdog::HW_TIMEOUT,
+};
+
+// Changed settings are saved once they did not change for this long,
+// so stepping through the brightness does not write every step.
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+// Commands from other tasks, handled by the UI task at the start of a frame.
+static QUEUE: Channel<CriticalSectionRawMutex, SystemCmd, 8> = Channel::new();
+
+/// Queue a command for the `SystemService`, for tasks other than the UI task.
+pub fn send(cmd: SystemCmd) {
+    if QUEUE.try_send(cmd).is_err() {
+        error!("System command queue is full");
+    }
+    request_wake();
+}
+
+/// Handles the system commands of apps and other tasks.
+///
+/// Owns the hardware the commands act on, so it lives in the UI task.
+pub struct SystemService<'a> {
+    display: DisplayDriver<'a>,
+    wdt: Wdt<TIMG1<'static>>,
+    settings: &'a RefCell<SystemSettings>,
+    toast: Toast,
+    // When the changed settings should be saved.
+    save_at: Option<Instant>,
+    // Returned by an app that was resumed after a system screen, see `take_app_response`.
+    app_response: Option<AppResponse>,
+}
+
+impl<'a> SystemService<'a> {
+    /// Arms the hardware watchdog, `feed_watchdog` has to be called at least every `HW_TIMEOUT`.
+    pub fn new(
+        display: DisplayDriver<'a>,
+        mut wdt: Wdt<TIMG1<'static>>,
+        settings: &'a RefCell<SystemSettings>,
+    ) -> Self {
+        wdt.set_timeout(MwdtStage::Stage0, HW_TIMEOUT);
+        wdt.enable();
+        Self {
+            display,
+            wdt,
+            settings,
+            toast: Toast::new(),
+            save_at: None,
+            app_response: None,
+        }
+    }
+    pub fn display(&mut self) -> &mut DisplayDriver<'a> {
+        &mut self.display
+    }
+    pub fn feed_watchdog(&mut self) {
+        self.wdt.feed();
+    }
+    /// The response of an app that a command resumed, to be applied like its other responses.
+    pub fn take_app_response(&mut self) -> Option<AppResponse> {
+        self.app_response.take()
+    }
+    pub fn toast(&self) -> &Toast {
+        &self.toast
+    }
+    pub fn show_toast(&mut self, text: &str) {
+        self.toast.show(text);
+    }
+    /// Hide the toast once its time is up, returns true when the screen has to be redrawn.
+    pub fn expire_toast(&mut self, now: Instant) -> bool {
+        self.toast.expire(now)
+    }
+    /// Apply the settings to the hardware and the grid, after loading or resetting them.
+    pub fn apply_settings(&mut self, grid: &mut ScreenGrid) {
+        let s = self.settings.borrow();
+        self.display.set_backlight(s.user_brightness);
+        self.display.set_rotation(s.rotation);
+        touch::set_flipped(s.rotation == ScreenRotation::Flipped);
+        touch::set_calibration(s.touch_calibration);
+        touch::set_sensitivity(s.touch_sensitivity);
+        grid.set_theme(s.theme);
+    }
+    /// When the changed settings will be saved.
+    pub fn save_deadline(&self) -> Option<Instant> {
+        self.save_at
+    }
+    /// Save the settings once `SAVE_DELAY` passed since the last change.
+    pub fn save_settings(&mut self, ctx: &mut Context, now: Instant) {
+        if self.save_at.is_some_and(|at| at <= now) {
+            self.flush_settings(ctx);
+        }
+    }
+    fn flush_settings(&mut self, ctx: &mut Context) {
+        if self.save_at.take().is_none() {
+            return;
+        }
+        let settings = self.settings.borrow();
+        match ctx
+            .storage
+            .with_namespace(SYSTEM_NAMESPACE, |storage| settings.save(storage))
+        {
+            Ok(()) => info!("Settings saved"),
+            Err(err) => error!("Failed to save settings: {:?}", err),
+        }
+    }
+    /// Store the files written during the updates, so the time it takes does not count for the app.
+    pub fn commit_storage(&mut self, ctx: &mut Context) {
+        if let Err(err) = ctx.storage.commit() {
+            error!("Failed to store files: {:?}", err);
+        }
+    }
+    fn settings_changed(&mut self) {
+        self.save_at = Some(Instant::now() + SAVE_DELAY);
+    }
+    /// Stop the CPU for `duration`, or until the screen is touched or a key is pressed.
+    pub fn light_sleep(&mut self, duration: Duration) {
+        let timer = TimerWakeupSource::new(core::time::Duration::from_micros(duration.as_micros()));
+        // The watchdog would bite after a long sleep, as nothing feeds it meanwhile.
+        self.wdt.disable();
+        touch::set_wakeup(true);
+        keys::set_wakeup(true);
+        clock::with_rtc(|rtc| rtc.sleep_light(&[&timer, &GpioWakeupSource::new()]));
+        touch::set_wakeup(false);
+        keys::set_wakeup(false);
+        self.wdt.enable();
+        // The touch or key press that woke the CPU did not raise its interrupt.
+        notify_input_irq();
+    }
+    // Deep sleep until the screen is touched, only returns without an RTC.
+    fn power_off(&mut self, ctx: &mut Context) -> SystemResult {
+        info!("Powering off");
+        self.flush_settings(ctx);
+        self.commit_storage(ctx);
+        let touch = touch::deep_sleep_wakeup();
+        // The screen only goes dark once the RTC is there to put the device to sleep.
+        let display = &mut self.display;
+        clock::with_rtc(|rtc| {
+            display.set_backlight(0);
+            display.set_sleeping(true);
+            rtc.sleep_deep(&[&touch]);
+        });
+        Err(SystemError::Unsupported)
+    }
+    /// Handle the commands queued by other tasks, returns true when any was handled.
+    pub fn process_queue(
+        &mut self,
+        ctx: &mut Context,
+        keyboard: &mut Keyboard,
+        apps: &mut AppManager,
+    ) -> bool {
+        let mut handled = false;
+        while let Ok(cmd) = QUEUE.try_receive() {
+            if let Err(err) = self.handle(cmd, ctx, keyboard, apps) {
+                warn!("System command failed: {:?}", err);
+            }
+            handled = true;
+        }
+        handled
+    }
+    pub fn handle(
+        &mut self,
+        cmd: SystemCmd,
+        ctx: &mut Context,
+        keyboard: &mut Keyboard,
+        apps: &mut AppManager,
+    ) -> SystemResult {
+        match cmd {
+            SystemCmd::StartCalibration => {
+                // The calibration targets are drawn for a screen that is not turned.
+                let rotation = self.settings.borrow().rotation;
+                self.display.set_rotation(ScreenRotation::Normal);
+                // The calibration waits for the user, the watchdog is fed while it does.
+                let wdt = &mut self.wdt;
+                let calibration =
+                    touch::recalibrate(ctx.grid, self.display.display_mut(), || wdt.feed());
+                self.display.set_rotation(rotation);
+
+                // The calibration screen replaced the app, so draw it again.
+                keyboard.close(ctx.buttons, ctx.grid);
+                ctx.grid.clear(' ', BASE03, BASE03);
+                self.app_response = Some(apps.on_resume(ctx));
+
+                let calibration = calibration.ok_or(SystemError::Unsupported)?;
+                info!("Touch calibration: {:?}", calibration);
+                self.settings.borrow_mut().touch_calibration = calibration;
+                self.settings_changed();
+            }
+            SystemCmd::ApplyCalibration(calibration) => {
+                if calibration.min_x >= calibration.max_x || calibration.min_y >= calibration.max_y
+                {
+                    return Err(SystemError::InvalidValue);
+                }
+                touch::set_calibration(calibration);
+                self.settings.borrow_mut().touch_calibration = calibration;
+                self.settings_changed();
+            }
+            SystemCmd::SetBrightness(val) => {
+                if val > 100 {
+                    return Err(SystemError::InvalidValue);
+                }
+                self.settings.borrow_mut().user_brightness = val;
+                self.display.set_backlight(val);
+                self.settings_changed();
+            }
+            SystemCmd::SetBacklight(val) => self.display.set_backlight(val.min(100)),
+            SystemCmd::OpenKeyboard(prompt, layout) => keyboard
+                .open(prompt, layout, ctx.buttons)
+                .map_err(|_| SystemError::Failed)?,
+            SystemCmd::SetKeyMapping(key, mapping) => {
+                self.settings.borrow_mut().set_key_mapping(key, mapping);
+                self.settings_changed();
+            }
+            SystemCmd::SetIdleTimeout(secs) => {
+                let mut s = self.settings.borrow_mut();
+                if secs == 0 || secs >= s.sleep_time {
+                    return Err(SystemError::InvalidValue);
+                }
+                s.idle_time = secs;
+                drop(s);
+                self.settings_changed();
+                // Wakes the power task, so it uses the new timeout.
+                report_activity();
+            }
+            SystemCmd::SetSleepTimeout(secs) => {
+                let mut s = self.settings.borrow_mut();
+                if secs <= s.idle_time || (s.off_time > 0 && secs >= s.off_time) {
+                    return Err(SystemError::InvalidValue);
+                }
+                s.sleep_time = secs;
+                drop(s);
+                self.settings_changed();
+                report_activity();
+            }
+            SystemCmd::SetPowerOffTimeout(secs) => {
+                let mut s = self.settings.borrow_mut();
+                if secs > 0 && secs <= s.sleep_time {
+                    return Err(SystemError::InvalidValue);
+                }
+                s.off_time = secs;
+                drop(s);
+                self.settings_changed();
+                report_activity();
+            }
+            SystemCmd::SetDisplaySleep(sleeping) => {
+                if !self.display.set_sleeping(sleeping) {
+                    return Err(SystemError::Failed);
+                }
+            }
+            SystemCmd::SetPowerProfile(profile) => {
+                self.settings.borrow_mut().power_profile = profile;
+                self.settings_changed();
+                report_activity();
+            }
+            SystemCmd::SetIdleBrightness(val) => {
+                if val > 100 {
+                    return Err(SystemError::InvalidValue);
+                }
+                self.settings.borrow_mut().idle_brightness = val;
+                self.settings_changed();
+                // Lets the power task set the backlight, in case it is idle.
+                recheck();
+            }
+            SystemCmd::SetTheme(theme) => {
+                self.settings.borrow_mut().theme = theme;
+                ctx.grid.set_theme(theme);
+                self.settings_changed();
+            }
+            SystemCmd::SetRotation(rotation) => {
+                if !self.display.set_rotation(rotation) {
+                    return Err(SystemError::Failed);
+                }
+                touch::set_flipped(rotation == ScreenRotation::Flipped);
+                ctx.grid.invalidate();
+                self.settings.borrow_mut().rotation = rotation;
+                self.settings_changed();
+            }
+            SystemCmd::SetNeverSleep(never_sleep) => {
+                self.settings.borrow_mut().never_sleep = never_sleep;
+                self.settings_changed();
+                report_activity();
+            }
+            SystemCmd::SetTouchSensitivity(sensitivity) => {
+                touch::set_sensitivity(sensitivity);
+                self.settings.borrow_mut().touch_sensitivity = sensitivity;
+                self.settings_changed();
+            }
+            SystemCmd::SetTime(local) => {
+                if !local.is_valid() {
+                    return Err(SystemError::InvalidValue);
+                }
+                let zone = self.settings.borrow().time_zone;
+                clock::set_utc(zone.to_utc(&local));
+                info!("Clock set to {}", local);
+            }
+            SystemCmd::SetTimeZone(zone) => {
+                if !zone.is_valid() {
+                    return Err(SystemError::InvalidValue);
+                }
+                self.settings.borrow_mut().time_zone = zone;
+                self.settings_changed();
+            }
+            SystemCmd::SetKeepTime(keep_time) => {
+                self.settings.borrow_mut().keep_time = keep_time;
+                self.settings_changed();
+            }
+            SystemCmd::FormatStorage => {
+                info!("Formatting storage");
+                let _boost = cpu::boost();
+                ctx.storage.format().map_err(|_| SystemError::Failed)?;
+                // The settings are kept, so save them again.
+                self.settings_changed();
+            }
+            SystemCmd::ResetSettings => {
+                *self.settings.borrow_mut() = SystemSettings::default();
+                self.apply_settings(ctx.grid);
+                self.settings_changed();
+                report_activity();
+            }
+            SystemCmd::ShowToast(text) => self.toast.show(&text),
+            SystemCmd::Screenshot => screenshot(ctx.grid),
+            SystemCmd::SetLogLevel(level) => {
+                log::set_max_level(level);
+                info!("Log level: {}", level);
+            }
+            SystemCmd::Reboot => {
+                info!("Rebooting...");
+                self.flush_settings(ctx);
+                self.commit_storage(ctx);
+                esp_hal::system::software_reset();
+            }
+            SystemCmd::PowerOff => return self.power_off(ctx),
+        }
+        Ok(())
+    }
+}
+
+// Log the characters on the screen, one line per row.
+fn screenshot(grid: &ScreenGrid) {
+    info!("Screenshot ({}x{}):", grid.cols, grid.rows);
+    for row in grid.cells.chunks(grid.cols as usize) {
+        let line: String<160> = row.iter().map(|cell| cell.ch).collect();
+        info!("|{}|", line);
+    }
+}
@@ -0,0 +1,223 @@
+use heapless::{String, Vec};
+
+use crate::journal::JournalFs;
+
+// Files of an app are stored as `<namespace>/<key>`.
+pub const MAX_KEY_LEN: usize = 16;
+pub const MAX_KEYS: usize = 8;
+
+// Bytes an app may store, excluding its index.
+pub const APP_QUOTA: usize = 1024;
+
+// Largest value, including the version byte.
+pub const MAX_VALUE_LEN: usize = 128;
+
+// Version byte of values written by the integer helpers.
+const INT_VERSION: u8 = 1;
+
+// Lists the keys of a namespace, one per line, to track the usage without listing the file system.
+const INDEX_KEY: &str = ".index";
+
+const MAX_PATH_LEN: usize = 32;
+const MAX_INDEX_LEN: usize = MAX_KEYS * (MAX_KEY_LEN + 1);
+
+#[derive(PartialEq, Debug)]
+pub enum StorageError {
+    InvalidKey,
+    TooManyKeys,
+    QuotaExceeded,
+    ValueTooLarge,
+    Fs,
+}
+
+/// A value that can be stored with `Storage::put_struct`.
+///
+/// Values are stored with their `VERSION`, so `decode` can still read data written by an older version.
+pub trait Record: Sized {
+    const VERSION: u8;
+    // Encoded size in bytes.
+    const SIZE: usize;
+
+    /// Write the value into `buf`, which is `SIZE` bytes long.
+    fn encode(&self, buf: &mut [u8]);
+    /// Read a value written with `version`, returns `None` for versions that are not supported.
+    fn decode(version: u8, buf: &[u8]) -> Option<Self>;
+}
+
+/// Storage of the active app.
+///
+/// Every app gets its own namespace on the `JournalFs`, so apps cannot read or overwrite each other's files.
+/// The namespace is switched by the `AppManager` together with the active app.
+pub struct Storage<'a> {
+    fs: &'a mut JournalFs,
+    namespace: &'static str,
+}
+
+impl<'a> Storage<'a> {
+    pub fn new(fs: &'a mut JournalFs, namespace: &'static str) -> Self {
+        Self { fs, namespace }
+    }
+    pub fn namespace(&self) -> &'static str {
+        self.namespace
+    }
+    pub(crate) fn set_namespace(&mut self, namespace: &'static str) {
+        self.namespace = namespace;
+    }
+    /// Run `f` with another namespace, for the system files.
+    pub(crate) fn with_namespace<R>(
+        &mut self,
+        namespace: &'static str,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let previous = core::mem::replace(&mut self.namespace, namespace);
+        let result = f(self);
+        self.namespace = previous;
+        result
+    }
+    pub fn read(&self, key: &str) -> Option<&[u8]> {
+        let path = self.path(key).ok()?;
+        self.fs.read(&path)
+    }
+    /// Writing no data removes the key.
+    pub fn write(&mut self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        if key == INDEX_KEY {
+            return Err(StorageError::InvalidKey);
+        }
+        if data.is_empty() {
+            return self.remove(key);
+        }
+        if data.len() > MAX_VALUE_LEN {
+            return Err(StorageError::ValueTooLarge);
+        }
+        let path = self.path(key)?;
+        let mut index = self.index();
+        let old_len = self.fs.read(&path).map_or(0, |old| old.len());
+
+        let is_new = !index.iter().any(|k| k == key);
+        if is_new && index.is_full() {
+            return Err(StorageError::TooManyKeys);
+        }
+        if self.usage().saturating_sub(old_len) + data.len() > APP_QUOTA {
+            return Err(StorageError::QuotaExceeded);
+        }
+
+        self.fs.write(&path, data).map_err(|_| StorageError::Fs)?;
+        if is_new {
+            let _ = index.push(String::try_from(key).map_err(|_| StorageError::InvalidKey)?);
+            self.write_index(&index)?;
+        }
+        Ok(())
+    }
+    /// Remove `key` and its value, frees its slot in the index.
+    pub fn remove(&mut self, key: &str) -> Result<(), StorageError> {
+        if key == INDEX_KEY {
+            return Err(StorageError::InvalidKey);
+        }
+        let path = self.path(key)?;
+        let mut index = self.index();
+        let Some(idx) = index.iter().position(|k| k == key) else {
+            return Ok(());
+        };
+        self.fs.remove(&path).map_err(|_| StorageError::Fs)?;
+        index.remove(idx);
+        self.write_index(&index)
+    }
+    /// Bytes written to the flash and its size, `None` when the files are only kept in RAM.
+    pub fn disk_usage(&self) -> Option<(u32, u32)> {
+        self.fs.usage()
+    }
+    /// Store the files written since the last commit on the flash, see `JournalFs::commit`.
+    pub(crate) fn commit(&mut self) -> Result<(), StorageError> {
+        self.fs.commit().map_err(|_| StorageError::Fs)
+    }
+    /// Remove the files of every namespace.
+    pub(crate) fn format(&mut self) -> Result<(), StorageError> {
+        self.fs.format().map_err(|_| StorageError::Fs)
+    }
+    /// Bytes used by the namespace.
+    pub fn usage(&self) -> usize {
+        self.index()
+            .iter()
+            .filter_map(|key| self.read(key))
+            .map(|data| data.len())
+            .sum()
+    }
+
+    pub fn get_u8(&self, key: &str) -> Option<u8> {
+        self.get_int::<1>(key).map(u8::from_be_bytes)
+    }
+    pub fn put_u8(&mut self, key: &str, value: u8) -> Result<(), StorageError> {
+        self.put_int(key, &value.to_be_bytes())
+    }
+    pub fn get_u16(&self, key: &str) -> Option<u16> {
+        self.get_int::<2>(key).map(u16::from_be_bytes)
+    }
+    pub fn put_u16(&mut self, key: &str, value: u16) -> Result<(), StorageError> {
+        self.put_int(key, &value.to_be_bytes())
+    }
+    pub fn get_u32(&self, key: &str) -> Option<u32> {
+        self.get_int::<4>(key).map(u32::from_be_bytes)
+    }
+    pub fn put_u32(&mut self, key: &str, value: u32) -> Result<(), StorageError> {
+        self.put_int(key, &value.to_be_bytes())
+    }
+    pub fn get_struct<T: Record>(&self, key: &str) -> Option<T> {
+        let (version, data) = self.read(key)?.split_first()?;
+        T::decode(*version, data)
+    }
+    pub fn put_struct<T: Record>(&mut self, key: &str, value: &T) -> Result<(), StorageError> {
+        let mut buf: Vec<u8, MAX_VALUE_LEN> = Vec::new();
+        buf.resize_default(T::SIZE + 1)
+            .map_err(|_| StorageError::ValueTooLarge)?;
+        buf[0] = T::VERSION;
+        value.encode(&mut buf[1..]);
+        self.write(key, &buf)
+    }
+
+    fn get_int<const N: usize>(&self, key: &str) -> Option<[u8; N]> {
+        match self.read(key)?.split_first()? {
+            (&INT_VERSION, bytes) => bytes.try_into().ok(),
+            _ => None,
+        }
+    }
+    fn put_int(&mut self, key: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        let mut buf: Vec<u8, 5> = Vec::new();
+        let _ = buf.push(INT_VERSION);
+        let _ = buf.extend_from_slice(bytes);
+        self.write(key, &buf)
+    }
+    fn path(&self, key: &str) -> Result<String<MAX_PATH_LEN>, StorageError> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN || key.contains(['/', '\n']) {
+            return Err(StorageError::InvalidKey);
+        }
+        let mut path = String::new();
+        path.push_str(self.namespace)
+            .and_then(|_| path.push('/'))
+            .and_then(|_| path.push_str(key))
+            .map_err(|_| StorageError::InvalidKey)?;
+        Ok(path)
+    }
+    fn index(&self) -> Vec<String<MAX_KEY_LEN>, MAX_KEYS> {
+        let mut index = Vec::new();
+        let Some(data) = self.read(INDEX_KEY) else {
+            return index;
+        };
+        for key in core::str::from_utf8(data).unwrap_or_default().lines() {
+            if let Ok(key) = String::try_from(key) {
+                let _ = index.push(key);
+            }
+        }
+        index
+    }
+    fn write_index(&mut self, index: &[String<MAX_KEY_LEN>]) -> Result<(), StorageError> {
+        let mut data: String<MAX_INDEX_LEN> = String::new();
+        for key in index {
+            let _ = data.push_str(key);
+            let _ = data.push('\n');
+        }
+        let path = self.path(INDEX_KEY)?;
+        self.fs
+            .write(&path, data.as_bytes())
+            .map_err(|_| StorageError::Fs)
+    }
+}
@@ -1,18 +1,196 @@
 use core::cell::RefCell;
 
-use crate::touch::TouchCalibration;
+use heapless::String;
+use log::{LevelFilter, warn};
 
+use crate::{
+    calendar::{DateTime, DstRule, TimeZone},
+    calibration::{TouchCalibration, TouchSensitivity},
+    debounce::{HwKey, HwKeyEvent, PressKind},
+    graphics::{ScreenRotation, TOAST_LEN, Theme},
+    keyboard::Layout,
+    power_state::PowerProfile,
+    storage::{Record, Storage, StorageError},
+};
+
+// Namespace of the system files, apps use their upper case name.
+pub const SYSTEM_NAMESPACE: &str = "system";
+const SETTINGS_KEY: &str = "settings";
+
+/// Requests to the system, handled by the `SystemService` of the UI task.
+///
+/// Apps send them with their `AppResponse`, other tasks with `service::send`.
+#[derive(PartialEq, Clone, Debug)]
 pub enum SystemCmd {
     StartCalibration,
     ApplyCalibration(TouchCalibration),
+    // Brightness chosen by the user, in percent.
     SetBrightness(u8),
+    // Backlight level for the power mode, does not change the user brightness.
+    SetBacklight(u8),
+    OpenKeyboard(&'static str, Layout),
+    SetKeyMapping(HwKey, KeyMapping),
+    // Seconds without activity before the screen dims or turns off.
+    SetIdleTimeout(u64),
+    SetSleepTimeout(u64),
+    // Seconds without activity before powering off, 0 to stay in sleep.
+    SetPowerOffTimeout(u64),
+    // Put the display controller in its sleep mode, or wake it up.
+    SetDisplaySleep(bool),
+    SetPowerProfile(PowerProfile),
+    // Backlight in the idle mode, in percent of the user brightness.
+    SetIdleBrightness(u8),
+    SetTheme(Theme),
+    SetRotation(ScreenRotation),
+    // Stay in the idle mode instead of turning the screen off.
+    SetNeverSleep(bool),
+    SetTouchSensitivity(TouchSensitivity),
+    // Local time in the time zone of the settings.
+    SetTime(DateTime),
+    SetTimeZone(TimeZone),
+    // Keep the time in RTC memory across resets.
+    SetKeepTime(bool),
+    // Erase the files of every app.
+    FormatStorage,
+    // Restore the default settings.
+    ResetSettings,
+    ShowToast(String<TOAST_LEN>),
+    // Write the screen contents to the log.
+    Screenshot,
+    SetLogLevel(LevelFilter),
+    Reboot,
+    // Deep sleep until the screen is touched, which restarts the firmware.
+    PowerOff,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SystemError {
+    InvalidValue,
+    Unsupported,
+    Failed,
+}
+
+/// Outcome of a `SystemCmd`, passed back to the app that sent it.
+pub type SystemResult = Result<(), SystemError>;
+
+/// What the system does with a hardware key press.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HwKeyAction {
+    // Pass the key event on to the active app.
+    App,
+    Home,
+    Back,
+    FocusNext,
+    FocusPrev,
+    Activate,
+}
+
+impl HwKeyAction {
+    // In the order of their stored value.
+    const ALL: [HwKeyAction; 6] = [
+        HwKeyAction::App,
+        HwKeyAction::Home,
+        HwKeyAction::Back,
+        HwKeyAction::FocusNext,
+        HwKeyAction::FocusPrev,
+        HwKeyAction::Activate,
+    ];
+
+    pub fn next(&self) -> Self {
+        match self {
+            HwKeyAction::App => HwKeyAction::Home,
+            HwKeyAction::Home => HwKeyAction::Back,
+            HwKeyAction::Back => HwKeyAction::FocusNext,
+            HwKeyAction::FocusNext => HwKeyAction::FocusPrev,
+            HwKeyAction::FocusPrev => HwKeyAction::Activate,
+            HwKeyAction::Activate => HwKeyAction::App,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            HwKeyAction::App => "App",
+            HwKeyAction::Home => "Home",
+            HwKeyAction::Back => "Back",
+            HwKeyAction::FocusNext => "Next",
+            HwKeyAction::FocusPrev => "Prev",
+            HwKeyAction::Activate => "Press",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct KeyMapping {
+    pub short: HwKeyAction,
+    pub long: HwKeyAction,
 }
 
+#[derive(PartialEq, Debug)]
 pub struct SystemSettings {
     pub user_brightness: u8,
     pub effective_brightness: u8,
     pub sleep_time: u64,
     pub idle_time: u64,
+    // 0 when the device never powers off by itself.
+    pub off_time: u64,
+    pub power_profile: PowerProfile,
+    // Percent of the user brightness in the idle mode.
+    pub idle_brightness: u8,
+    pub theme: Theme,
+    pub rotation: ScreenRotation,
+    pub never_sleep: bool,
+    pub touch_sensitivity: TouchSensitivity,
+    pub touch_calibration: TouchCalibration,
+    pub boot_key: KeyMapping,
+    pub user_key: KeyMapping,
+    pub time_zone: TimeZone,
+    pub keep_time: bool,
+}
+
+impl SystemSettings {
+    /// The saved settings, or the defaults when there are none or they cannot be read.
+    pub fn load(storage: &Storage) -> Self {
+        match storage.get_struct::<SystemSettings>(SETTINGS_KEY) {
+            Some(settings) => settings,
+            None => {
+                if storage.read(SETTINGS_KEY).is_some() {
+                    warn!("Saved settings are invalid, using the defaults");
+                }
+                SystemSettings::default()
+            }
+        }
+    }
+    /// Save the settings, `storage` has to use the `SYSTEM_NAMESPACE`.
+    pub fn save(&self, storage: &mut Storage) -> Result<(), StorageError> {
+        storage.put_struct(SETTINGS_KEY, self)
+    }
+    pub fn key_mapping(&self, key: HwKey) -> KeyMapping {
+        match key {
+            HwKey::Boot => self.boot_key,
+            HwKey::User => self.user_key,
+        }
+    }
+    pub fn set_key_mapping(&mut self, key: HwKey, mapping: KeyMapping) {
+        match key {
+            HwKey::Boot => self.boot_key = mapping,
+            HwKey::User => self.user_key = mapping,
+        }
+    }
+    /// System action for a key event, `None` when it should be ignored.
+    ///
+    /// Repeats follow the long press that started them, but only to move the focus or for the app.
+    pub fn key_action(&self, event: HwKeyEvent) -> Option<HwKeyAction> {
+        let mapping = self.key_mapping(event.key);
+        match event.press {
+            PressKind::Short => Some(mapping.short),
+            PressKind::Long => Some(mapping.long),
+            PressKind::Repeat => match mapping.long {
+                HwKeyAction::App | HwKeyAction::FocusNext | HwKeyAction::FocusPrev => {
+                    Some(mapping.long)
+                }
+                _ => None,
+            },
+        }
+    }
 }
 
 impl Default for SystemSettings {
@@ -22,10 +200,164 @@ impl Default for SystemSettings {
             effective_brightness: 100,
             sleep_time: 60,
             idle_time: 10,
+            off_time: 0,
+            power_profile: PowerProfile::Balanced,
+            idle_brightness: 50,
+            theme: Theme::Dark,
+            rotation: ScreenRotation::Normal,
+            never_sleep: false,
+            touch_sensitivity: TouchSensitivity::Medium,
+            touch_calibration: TouchCalibration::default(),
+            boot_key: KeyMapping {
+                short: HwKeyAction::Back,
+                long: HwKeyAction::Home,
+            },
+            user_key: KeyMapping {
+                short: HwKeyAction::FocusNext,
+                long: HwKeyAction::Activate,
+            },
+            time_zone: TimeZone::UTC,
+            keep_time: true,
         }
     }
 }
 
+// Layout of version 5:
+// user brightness, idle time (u32), sleep time (u32), theme, the short and long action of the boot and user key,
+// followed by the screen rotation, never sleep, touch sensitivity and the touch calibration (4 x u16),
+// then the UTC offset in minutes (i16), the DST rule, keep time, the power off time (u32),
+// the power profile and the idle brightness.
+// Version 1 ends after the key actions, version 2 after the touch calibration, version 3 after keep time
+// and version 4 after the power off time.
+// The effective brightness is set by the power manager, so it is not saved.
+impl Record for SystemSettings {
+    const VERSION: u8 = 5;
+    const SIZE: usize = 35;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.user_brightness;
+        buf[1..5].copy_from_slice(&(self.idle_time.min(u32::MAX as u64) as u32).to_be_bytes());
+        buf[5..9].copy_from_slice(&(self.sleep_time.min(u32::MAX as u64) as u32).to_be_bytes());
+        buf[9] = self.theme as u8;
+        buf[10] = self.boot_key.short as u8;
+        buf[11] = self.boot_key.long as u8;
+        buf[12] = self.user_key.short as u8;
+        buf[13] = self.user_key.long as u8;
+        buf[14] = self.rotation as u8;
+        buf[15] = self.never_sleep as u8;
+        buf[16] = self.touch_sensitivity as u8;
+        let cal = &self.touch_calibration;
+        for (idx, value) in [cal.min_x, cal.min_y, cal.max_x, cal.max_y]
+            .iter()
+            .enumerate()
+        {
+            buf[17 + idx * 2..19 + idx * 2].copy_from_slice(&value.to_be_bytes());
+        }
+        buf[25..27].copy_from_slice(&self.time_zone.offset_min.to_be_bytes());
+        buf[27] = self.time_zone.dst as u8;
+        buf[28] = self.keep_time as u8;
+        buf[29..33].copy_from_slice(&(self.off_time.min(u32::MAX as u64) as u32).to_be_bytes());
+        buf[33] = self.power_profile as u8;
+        buf[34] = self.idle_brightness;
+    }
+    fn decode(version: u8, buf: &[u8]) -> Option<Self> {
+        // Each version reads the layout of the previous one, fields it does not have keep their default.
+        let settings = match version {
+            1 if buf.len() == V1_SIZE => decode_v1(buf)?,
+            2 if buf.len() == V2_SIZE => decode_v2(buf)?,
+            3 if buf.len() == V3_SIZE => decode_v3(buf)?,
+            4 if buf.len() == V4_SIZE => decode_v4(buf)?,
+            5 if buf.len() == Self::SIZE => decode_v5(buf)?,
+            _ => return None,
+        };
+        let cal = &settings.touch_calibration;
+        let valid = settings.user_brightness <= 100
+            && settings.idle_brightness <= 100
+            && settings.idle_time > 0
+            && settings.idle_time < settings.sleep_time
+            && (settings.off_time == 0 || settings.off_time > settings.sleep_time)
+            && cal.min_x < cal.max_x
+            && cal.min_y < cal.max_y
+            && settings.time_zone.is_valid();
+        valid.then_some(settings)
+    }
+}
+
+const V1_SIZE: usize = 14;
+const V2_SIZE: usize = 25;
+const V3_SIZE: usize = 29;
+const V4_SIZE: usize = 33;
+
+fn flag(byte: u8) -> Option<bool> {
+    match byte {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+fn decode_v1(buf: &[u8]) -> Option<SystemSettings> {
+    let action = |byte: u8| HwKeyAction::ALL.get(byte as usize).copied();
+    Some(SystemSettings {
+        user_brightness: buf[0],
+        effective_brightness: buf[0],
+        idle_time: u32::from_be_bytes(buf[1..5].try_into().ok()?) as u64,
+        sleep_time: u32::from_be_bytes(buf[5..9].try_into().ok()?) as u64,
+        theme: Theme::ALL.get(buf[9] as usize).copied()?,
+        boot_key: KeyMapping {
+            short: action(buf[10])?,
+            long: action(buf[11])?,
+        },
+        user_key: KeyMapping {
+            short: action(buf[12])?,
+            long: action(buf[13])?,
+        },
+        ..SystemSettings::default()
+    })
+}
+
+fn decode_v2(buf: &[u8]) -> Option<SystemSettings> {
+    let word = |idx: usize| u16::from_be_bytes([buf[17 + idx * 2], buf[18 + idx * 2]]);
+    Some(SystemSettings {
+        rotation: ScreenRotation::ALL.get(buf[14] as usize).copied()?,
+        never_sleep: flag(buf[15])?,
+        touch_sensitivity: TouchSensitivity::ALL.get(buf[16] as usize).copied()?,
+        touch_calibration: TouchCalibration {
+            min_x: word(0),
+            min_y: word(1),
+            max_x: word(2),
+            max_y: word(3),
+        },
+        ..decode_v1(&buf[..V1_SIZE])?
+    })
+}
+
+fn decode_v3(buf: &[u8]) -> Option<SystemSettings> {
+    Some(SystemSettings {
+        time_zone: TimeZone {
+            offset_min: i16::from_be_bytes([buf[25], buf[26]]),
+            dst: DstRule::ALL.get(buf[27] as usize).copied()?,
+        },
+        keep_time: flag(buf[28])?,
+        ..decode_v2(&buf[..V2_SIZE])?
+    })
+}
+
+fn decode_v4(buf: &[u8]) -> Option<SystemSettings> {
+    Some(SystemSettings {
+        off_time: u32::from_be_bytes(buf[29..33].try_into().ok()?) as u64,
+        ..decode_v3(&buf[..V3_SIZE])?
+    })
+}
+
+fn decode_v5(buf: &[u8]) -> Option<SystemSettings> {
+    Some(SystemSettings {
+        power_profile: PowerProfile::ALL.get(buf[33] as usize).copied()?,
+        idle_brightness: buf[34],
+        ..decode_v4(&buf[..V4_SIZE])?
+    })
+}
+
 #[derive(Copy, Clone)]
 pub struct SettingsView<'a> {
     inner: &'a RefCell<SystemSettings>,
@@ -40,3 +372,179 @@ impl<'a> SettingsView<'a> {
         f(&s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key: HwKey, press: PressKind) -> HwKeyEvent {
+        HwKeyEvent { key, press }
+    }
+
+    // Every field differs from the default.
+    fn custom() -> SystemSettings {
+        SystemSettings {
+            user_brightness: 40,
+            effective_brightness: 40,
+            sleep_time: 300,
+            idle_time: 30,
+            off_time: 3600,
+            power_profile: PowerProfile::Saver,
+            idle_brightness: 20,
+            theme: Theme::Light,
+            rotation: ScreenRotation::Flipped,
+            never_sleep: true,
+            touch_sensitivity: TouchSensitivity::High,
+            touch_calibration: TouchCalibration {
+                min_x: 100,
+                min_y: 200,
+                max_x: 3900,
+                max_y: 3800,
+            },
+            boot_key: KeyMapping {
+                short: HwKeyAction::FocusPrev,
+                long: HwKeyAction::App,
+            },
+            user_key: KeyMapping {
+                short: HwKeyAction::Home,
+                long: HwKeyAction::Back,
+            },
+            time_zone: TimeZone {
+                offset_min: -150,
+                dst: DstRule::NorthAmerica,
+            },
+            keep_time: false,
+        }
+    }
+
+    fn encoded(settings: &SystemSettings) -> [u8; SystemSettings::SIZE] {
+        let mut buf = [0; SystemSettings::SIZE];
+        settings.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let settings = custom();
+        let buf = encoded(&settings);
+        assert_eq!(
+            SystemSettings::decode(SystemSettings::VERSION, &buf),
+            Some(custom())
+        );
+        assert_eq!(
+            SystemSettings::decode(
+                SystemSettings::VERSION,
+                &encoded(&SystemSettings::default())
+            ),
+            Some(SystemSettings::default())
+        );
+    }
+
+    #[test]
+    fn older_versions_are_migrated() {
+        let buf = encoded(&custom());
+        let defaults = SystemSettings::default();
+
+        let v1 = SystemSettings::decode(1, &buf[..V1_SIZE]).unwrap();
+        assert_eq!(v1.user_brightness, 40);
+        assert_eq!(v1.idle_time, 30);
+        assert_eq!(v1.sleep_time, 300);
+        assert_eq!(v1.theme, Theme::Light);
+        assert_eq!(v1.boot_key, custom().boot_key);
+        assert_eq!(v1.user_key, custom().user_key);
+        assert_eq!(v1.rotation, defaults.rotation);
+        assert_eq!(v1.touch_calibration, defaults.touch_calibration);
+
+        let v2 = SystemSettings::decode(2, &buf[..V2_SIZE]).unwrap();
+        assert_eq!(v2.rotation, ScreenRotation::Flipped);
+        assert!(v2.never_sleep);
+        assert_eq!(v2.touch_sensitivity, TouchSensitivity::High);
+        assert_eq!(v2.touch_calibration, custom().touch_calibration);
+        assert_eq!(v2.time_zone, defaults.time_zone);
+        assert_eq!(v2.keep_time, defaults.keep_time);
+
+        let v3 = SystemSettings::decode(3, &buf[..V3_SIZE]).unwrap();
+        assert_eq!(v3.time_zone, custom().time_zone);
+        assert!(!v3.keep_time);
+        assert_eq!(v3.off_time, defaults.off_time);
+
+        let v4 = SystemSettings::decode(4, &buf[..V4_SIZE]).unwrap();
+        assert_eq!(v4.off_time, 3600);
+        assert_eq!(v4.power_profile, defaults.power_profile);
+        assert_eq!(v4.idle_brightness, defaults.idle_brightness);
+    }
+
+    #[test]
+    fn bad_enum_bytes_are_rejected() {
+        // Theme, key actions, rotation, sensitivity, DST rule and power profile.
+        for idx in [9, 10, 11, 12, 13, 14, 16, 27, 33] {
+            let mut buf = encoded(&custom());
+            buf[idx] = 0xff;
+            assert_eq!(SystemSettings::decode(SystemSettings::VERSION, &buf), None);
+        }
+        // Flags are 0 or 1.
+        for idx in [15, 28] {
+            let mut buf = encoded(&custom());
+            buf[idx] = 2;
+            assert_eq!(SystemSettings::decode(SystemSettings::VERSION, &buf), None);
+        }
+    }
+
+    #[test]
+    fn wrong_lengths_are_rejected() {
+        let buf = encoded(&custom());
+        let version = SystemSettings::VERSION;
+        assert_eq!(SystemSettings::decode(version, &buf[..V4_SIZE]), None);
+        assert_eq!(SystemSettings::decode(4, &buf), None);
+        assert_eq!(SystemSettings::decode(1, &buf[..V2_SIZE]), None);
+        assert_eq!(SystemSettings::decode(0, &buf[..V1_SIZE]), None);
+        assert_eq!(SystemSettings::decode(version + 1, &buf), None);
+        assert_eq!(SystemSettings::decode(version, &[]), None);
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let invalid: [fn(&mut SystemSettings); 9] = [
+            |s| s.user_brightness = 101,
+            |s| s.idle_brightness = 101,
+            |s| s.idle_time = 0,
+            |s| s.idle_time = s.sleep_time,
+            |s| s.off_time = s.sleep_time,
+            |s| s.touch_calibration.max_x = s.touch_calibration.min_x,
+            |s| s.touch_calibration.min_y = s.touch_calibration.max_y + 1,
+            |s| s.time_zone.offset_min = 7,
+            |s| s.time_zone.offset_min = TimeZone::MAX_OFFSET + 15,
+        ];
+        for change in invalid {
+            let mut settings = custom();
+            change(&mut settings);
+            let buf = encoded(&settings);
+            assert_eq!(SystemSettings::decode(SystemSettings::VERSION, &buf), None);
+        }
+    }
+
+    #[test]
+    fn repeat_follows_the_long_press() {
+        let mut settings = SystemSettings::default();
+        // USER defaults to FocusNext and Activate, holding it must not move the focus.
+        assert_eq!(
+            settings.key_action(event(HwKey::User, PressKind::Long)),
+            Some(HwKeyAction::Activate)
+        );
+        assert_eq!(
+            settings.key_action(event(HwKey::User, PressKind::Repeat)),
+            None
+        );
+
+        settings.user_key.long = HwKeyAction::FocusPrev;
+        assert_eq!(
+            settings.key_action(event(HwKey::User, PressKind::Repeat)),
+            Some(HwKeyAction::FocusPrev)
+        );
+        settings.user_key.long = HwKeyAction::App;
+        assert_eq!(
+            settings.key_action(event(HwKey::User, PressKind::Repeat)),
+            Some(HwKeyAction::App)
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use esp_hal::time::{Duration, Instant};
+use heapless::Vec;
+use log::error;
+
+pub const MAX_TIMERS: usize = 8;
+
+// Shortest period of a repeating timer, a zero period would fire on every call of `pop_expired`.
+pub const MIN_PERIOD: Duration = Duration::from_millis(10);
+
+/// Chosen by the app, delivered back in `InputEvents::timer`.
+pub type TimerId = u8;
+
+struct Timer {
+    id: TimerId,
+    deadline: Instant,
+    // `None` for one-shot timers.
+    period: Option<Duration>,
+}
+
+/// Timers of the active app.
+///
+/// Cleared when switching apps, apps register their timers again in `init` or `on_resume`.
+pub struct TimerService {
+    timers: Vec<Timer, MAX_TIMERS>,
+}
+
+impl TimerService {
+    pub const fn new() -> Self {
+        Self { timers: Vec::new() }
+    }
+    /// Fire `id` once after `delay`. Replaces a running timer with the same id.
+    pub fn start_oneshot(&mut self, id: TimerId, delay: Duration) {
+        self.start(id, delay, None);
+    }
+    /// Fire `id` every `period`, at least `MIN_PERIOD`. Replaces a running timer with the same id.
+    pub fn start_repeating(&mut self, id: TimerId, period: Duration) {
+        let period = period.max(MIN_PERIOD);
+        self.start(id, period, Some(period));
+    }
+    pub fn cancel(&mut self, id: TimerId) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+    pub fn is_running(&self, id: TimerId) -> bool {
+        self.timers.iter().any(|timer| timer.id == id)
+    }
+    pub fn clear(&mut self) {
+        self.timers.clear();
+    }
+    /// The earliest time a timer fires.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.iter().map(|timer| timer.deadline).min()
+    }
+    /// Take the earliest expired timer, repeating timers are scheduled again.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<TimerId> {
+        let (idx, timer) = self
+            .timers
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .min_by_key(|(_, timer)| timer.deadline)?;
+        let id = timer.id;
+
+        match timer.period {
+            Some(period) => {
+                timer.deadline += period;
+                // Skip the missed ticks instead of firing them all at once after a stall.
+                if timer.deadline <= now {
+                    timer.deadline = now + period;
+                }
+            }
+            None => {
+                self.timers.swap_remove(idx);
+            }
+        }
+        Some(id)
+    }
+
+    fn start(&mut self, id: TimerId, delay: Duration, period: Option<Duration>) {
+        self.cancel(id);
+        let timer = Timer {
+            id,
+            deadline: Instant::now() + delay,
+            period,
+        };
+        if self.timers.push(timer).is_err() {
+            error!("No free timer for id {}", id);
+        }
+    }
+}
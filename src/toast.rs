@@ -0,0 +1,54 @@
+use esp_hal::time::{Duration, Instant};
+use heapless::String;
+
+pub use crate::graphics::TOAST_LEN;
+use crate::graphics::{BASE3, RED, ScreenGrid};
+
+// How long a toast stays visible.
+const TOAST_TIME: Duration = Duration::from_secs(3);
+
+// Drawn over the status bar, which is redrawn on every render.
+const TOAST_ROW: u16 = 31;
+
+/// A short message shown over the status bar for a few seconds.
+pub struct Toast {
+    text: String<TOAST_LEN>,
+    until: Option<Instant>,
+}
+
+impl Toast {
+    pub const fn new() -> Self {
+        Self {
+            text: String::new(),
+            until: None,
+        }
+    }
+    pub fn show(&mut self, text: &str) {
+        self.text.clear();
+        for c in text.chars() {
+            if self.text.push(c).is_err() {
+                break;
+            }
+        }
+        self.until = Some(Instant::now() + TOAST_TIME);
+    }
+    /// When the toast should be hidden.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.until
+    }
+    /// Hide the toast once its time is up, returns true when it was hidden.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.until.is_some_and(|until| until <= now) {
+            self.until = None;
+            return true;
+        }
+        false
+    }
+    pub fn render(&self, grid: &mut ScreenGrid) {
+        if self.until.is_none() {
+            return;
+        }
+        grid.draw_box(0, TOAST_ROW, grid.cols, 1, RED);
+        grid.write_str(0, TOAST_ROW, &self.text, BASE3, RED);
+    }
+}
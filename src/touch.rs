@@ -1,46 +1,31 @@
+use core::cell::RefCell;
 use core::convert::Infallible;
+use critical_section::Mutex;
 use embedded_hal::digital::OutputPin;
 use embedded_hal::spi::SpiBus;
 
+use crate::calibration::map_touch;
+use crate::events::{InputEvent, notify_input_irq, push_event};
 use crate::graphics::*;
 use esp_hal::delay::Delay;
-use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig};
+use esp_hal::gpio::{Event, Input, InputConfig, Level, Output, OutputConfig, WakeEvent};
+use esp_hal::peripherals::GPIO9;
+use esp_hal::rtc_cntl::sleep::{Ext0WakeupSource, WakeupLevel};
 use esp_hal::spi::master::{Config, Spi};
 use esp_hal::time::{Instant, Rate};
 
-// TODO: Move touch calibration out of input
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::DrawTarget;
 use esp_hal::{Blocking, DriverMode};
 use log::info;
 
-#[derive(PartialEq)]
-pub enum TouchEvent {
-    Down { x: u16, y: u16 },
-    Move { x: u16, y: u16 },
-    Up,
-}
-
-pub struct TouchCalibration {
-    pub min_x: u16,
-    pub min_y: u16,
-    pub max_x: u16,
-    pub max_y: u16,
-}
-
-impl Default for TouchCalibration {
-    fn default() -> Self {
-        Self {
-            min_x: 314,
-            min_y: 297,
-            max_x: 3707,
-            max_y: 3656,
-        }
-    }
-}
+pub use crate::calibration::{TouchCalibration, TouchSensitivity};
+pub use crate::input::TouchEvent;
 
 pub const X_AXIS: u8 = 0xD0;
 pub const Y_AXIS: u8 = 0x90;
+// Pressure, rises with the force of the touch.
+pub const Z1_AXIS: u8 = 0xB0;
 
 /// Read one axis (X or Y) from XPT2046 using the given command
 /// - cmd: 0xD0 for X, 0x90 for Y (12-bit differential mode)
@@ -108,25 +93,33 @@ impl<'a> TouchDriver<'a> {
 
 pub struct TouchPoller<'a> {
     calibration: TouchCalibration,
-    driver: &'a mut TouchDriver<'a>,
+    sensitivity: TouchSensitivity,
+    // The screen is turned 180 degrees.
+    flipped: bool,
+    driver: TouchDriver<'a>,
     touch_down: bool,
 }
 
 impl<'a> TouchPoller<'a> {
-    pub fn new(calibration: TouchCalibration, driver: &'a mut TouchDriver<'a>) -> Self {
+    pub fn new(calibration: TouchCalibration, driver: TouchDriver<'a>) -> Self {
         Self {
             calibration,
+            sensitivity: TouchSensitivity::Medium,
+            flipped: false,
             driver,
             touch_down: false,
         }
     }
     pub fn poll(&mut self) -> Option<TouchEvent> {
-        if self.driver.t_irq.is_low() {
+        if self.driver.t_irq.is_low() && self.is_pressed() {
             if let (Ok(x_raw), Ok(y_raw)) = (
                 xpt2046_read_axis(&mut self.driver.touch_spi, &mut self.driver.t_cs, 0xD0),
                 xpt2046_read_axis(&mut self.driver.touch_spi, &mut self.driver.t_cs, 0x90),
             ) {
-                let (x, y) = map_touch(x_raw, y_raw, &self.calibration);
+                let (mut x, mut y) = map_touch(x_raw, y_raw, &self.calibration);
+                if self.flipped {
+                    (x, y) = (239 - x.min(239), 319 - y.min(319));
+                }
                 if self.touch_down {
                     return Some(TouchEvent::Move { x, y });
                 } else {
@@ -140,23 +133,146 @@ impl<'a> TouchPoller<'a> {
         }
         None
     }
+    pub fn is_touching(&self) -> bool {
+        self.touch_down
+    }
+    // Whether the touch is firm enough for the sensitivity.
+    fn is_pressed(&mut self) -> bool {
+        xpt2046_read_axis(&mut self.driver.touch_spi, &mut self.driver.t_cs, Z1_AXIS)
+            .is_ok_and(|z1| z1 >= self.sensitivity.min_pressure())
+    }
 }
 
-fn map(raw: u16, min: u16, max: u16, out_max: u16) -> u16 {
-    if max <= min || raw < min {
-        return 0;
-    }
+// Shared between the touch IRQ handler and the main loop.
+static TOUCH: Mutex<RefCell<Option<TouchPoller<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// Move the poller into the interrupt context and start listening on the touch IRQ line.
+///
+/// New touches are sampled directly from the interrupt and pushed into the event queue,
+/// so a tap is not lost while the main loop is waiting for the next frame.
+pub fn install_touch_irq(mut poller: TouchPoller<'static>) {
+    critical_section::with(|cs| {
+        poller.driver.t_irq.listen(Event::FallingEdge);
+        TOUCH.borrow_ref_mut(cs).replace(poller);
+    });
+}
+
+/// Let a touch wake the CPU from light sleep, instead of raising the interrupt.
+pub fn set_wakeup(enable: bool) {
+    critical_section::with(|cs| {
+        if let Some(poller) = TOUCH.borrow_ref_mut(cs).as_mut() {
+            let t_irq = &mut poller.driver.t_irq;
+            // The IRQ line stays low for as long as the screen is touched.
+            let _ = t_irq.wakeup_enable(enable, WakeEvent::LowLevel);
+            if !enable {
+                t_irq.listen(Event::FallingEdge);
+            }
+        }
+    });
+}
 
-    let num = (raw - min) as u32 * out_max as u32;
-    let den = (max - min) as u32;
+/// The touch IRQ line as wake-up source for deep sleep, which restarts the firmware.
+pub fn deep_sleep_wakeup() -> Ext0WakeupSource<GPIO9<'static>> {
+    // SAFETY: Only the RTC reads the pin from here on, the poller is gone after the restart.
+    let irq = unsafe { GPIO9::steal() };
+    Ext0WakeupSource::new(irq, WakeupLevel::Low)
+}
 
-    (num / den) as u16
+/// Replace the calibration of the installed poller.
+pub fn set_calibration(calibration: TouchCalibration) {
+    critical_section::with(|cs| {
+        if let Some(poller) = TOUCH.borrow_ref_mut(cs).as_mut() {
+            poller.calibration = calibration;
+        }
+    });
 }
 
-fn map_touch(raw_x: u16, raw_y: u16, calibration: &TouchCalibration) -> (u16, u16) {
-    let x = map(raw_x, calibration.min_x, calibration.max_x, 239);
-    let y = map(raw_y, calibration.min_y, calibration.max_y, 319);
-    (x, y)
+/// Change how firm a touch has to be for the installed poller.
+pub fn set_sensitivity(sensitivity: TouchSensitivity) {
+    critical_section::with(|cs| {
+        if let Some(poller) = TOUCH.borrow_ref_mut(cs).as_mut() {
+            poller.sensitivity = sensitivity;
+        }
+    });
+}
+
+/// Turn the touch coordinates with a screen that is turned 180 degrees.
+pub fn set_flipped(flipped: bool) {
+    critical_section::with(|cs| {
+        if let Some(poller) = TOUCH.borrow_ref_mut(cs).as_mut() {
+            poller.flipped = flipped;
+        }
+    });
+}
+
+/// Run `calibrate_touch` with the installed poller, returns `None` without one.
+///
+/// Blocks until the calibration is done, the touch interrupt is off in the meantime.
+/// `feed` is called on every screen update, to keep the watchdog from resetting the device.
+pub fn recalibrate<D: DrawTarget<Color = Rgb565>>(
+    screen_grid: &mut ScreenGrid,
+    display: &mut D,
+    feed: impl FnMut(),
+) -> Option<TouchCalibration> {
+    let mut poller = critical_section::with(|cs| {
+        let mut poller = TOUCH.borrow_ref_mut(cs).take()?;
+        poller.driver.t_irq.unlisten();
+        Some(poller)
+    })?;
+
+    let driver = &mut poller.driver;
+    let calibration = calibrate_touch(
+        &driver.t_irq,
+        &mut driver.touch_spi,
+        &mut driver.t_cs,
+        screen_grid,
+        display,
+        feed,
+    );
+    poller.calibration = calibration;
+    install_touch_irq(poller);
+    Some(calibration)
+}
+
+/// Sample the touch controller and queue any event, used for moves and releases.
+///
+/// Returns true while touching, moves are only seen by polling.
+pub fn poll_touch() -> bool {
+    critical_section::with(|cs| {
+        let mut touch = TOUCH.borrow_ref_mut(cs);
+        let Some(poller) = touch.as_mut() else {
+            return false;
+        };
+        if let Some(event) = poller.poll() {
+            push_event(InputEvent::Touch(event));
+        }
+        poller.is_touching()
+    })
+}
+
+/// Called from the GPIO interrupt handler.
+pub fn on_touch_interrupt() {
+    critical_section::with(|cs| {
+        let mut touch = TOUCH.borrow_ref_mut(cs);
+        let Some(poller) = touch.as_mut() else {
+            return;
+        };
+
+        if !poller.driver.t_irq.is_interrupt_set() {
+            return;
+        }
+        poller.driver.t_irq.clear_interrupt();
+
+        // PENIRQ can glitch during conversions, only new touches are handled here.
+        if poller.is_touching() {
+            return;
+        }
+        if let Some(event) = poller.poll() {
+            push_event(InputEvent::Touch(event));
+            // Moves and the release are polled by the input task.
+            notify_input_irq();
+        }
+    });
 }
 
 pub fn calibrate_touch<D: DrawTarget<Color = Rgb565>, DM: DriverMode>(
@@ -165,6 +281,7 @@ pub fn calibrate_touch<D: DrawTarget<Color = Rgb565>, DM: DriverMode>(
     mut t_cs: &mut Output,
     screen_grid: &mut ScreenGrid,
     display: &mut D,
+    mut feed: impl FnMut(),
 ) -> TouchCalibration {
     let mut calibration = TouchCalibration {
         min_x: 0,
@@ -178,6 +295,7 @@ pub fn calibrate_touch<D: DrawTarget<Color = Rgb565>, DM: DriverMode>(
     let delay = Delay::new();
     let mut calibration_step = 0;
     while calibration_step < 4 {
+        feed();
         let state = t_irq.is_low();
 
         if state {
@@ -0,0 +1,195 @@
+use core::{fmt::Write, panic::Location};
+
+use esp_hal::{
+    ram,
+    rtc_cntl::SocResetReason,
+    time::{Duration, Instant},
+};
+use heapless::String;
+use log::error;
+
+use crate::crash::{self, APP_LEN, CrashReport, LOCATION_LEN};
+
+// Longest an app may take for one call before it is closed.
+pub const UPDATE_BUDGET: Duration = Duration::from_millis(100);
+pub const RENDER_BUDGET: Duration = Duration::from_millis(100);
+
+// The hardware watchdog resets the device when it is not fed within `HW_TIMEOUT`.
+// The main loop runs at least every `FEED_INTERVAL`, also when there is nothing to do.
+pub const HW_TIMEOUT: Duration = Duration::from_secs(5);
+pub const FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+// Marks a valid record, RTC RAM holds random data after power on.
+const MAGIC: u32 = 0x5744_4f47;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AppCall {
+    Update,
+    Render,
+}
+
+impl AppCall {
+    pub fn name(self) -> &'static str {
+        match self {
+            AppCall::Update => "update",
+            AppCall::Render => "render",
+        }
+    }
+    fn budget(self) -> Duration {
+        match self {
+            AppCall::Update => UPDATE_BUDGET,
+            AppCall::Render => RENDER_BUDGET,
+        }
+    }
+}
+
+/// An app call that took longer than its budget.
+#[derive(Clone, Copy, Debug)]
+pub struct Overrun {
+    pub app: &'static str,
+    pub call: AppCall,
+    pub took: Duration,
+}
+
+// The app call that is running, so a reset by the hardware watchdog can be blamed on the app.
+#[repr(C)]
+struct CallRecord {
+    magic: u32,
+    // When the call started.
+    uptime_ms: u64,
+    // 0 when no call is running, otherwise `AppCall as u8 + 1`.
+    call: u8,
+    app_len: u8,
+    location_len: u8,
+    app: [u8; APP_LEN],
+    // Where the app was called from.
+    location: [u8; LOCATION_LEN],
+}
+
+// SAFETY: Only integers and arrays of integers.
+unsafe impl esp_hal::Persistable for CallRecord {}
+
+#[ram(unstable(rtc_fast, persistent))]
+static mut RUNNING_CALL: CallRecord = CallRecord {
+    magic: 0,
+    uptime_ms: 0,
+    call: 0,
+    app_len: 0,
+    location_len: 0,
+    app: [0; APP_LEN],
+    location: [0; LOCATION_LEN],
+};
+
+/// Measures how long the app takes for each call.
+///
+/// The time is only checked after the call returned, a slow call is not interrupted. A call that never
+/// returns is caught by the hardware watchdog instead, `begin` records the call in RTC RAM so
+/// `check_reset` can report it after restarting.
+pub struct AppWatchdog {
+    running: Option<(&'static str, AppCall, Instant)>,
+}
+
+impl AppWatchdog {
+    pub const fn new() -> Self {
+        Self { running: None }
+    }
+    #[track_caller]
+    pub fn begin(&mut self, app: &'static str, call: AppCall) {
+        let start = Instant::now();
+        self.running = Some((app, call, start));
+
+        let caller = Location::caller();
+        let mut location: String<LOCATION_LEN> = String::new();
+        let _ = write!(location, "{}:{}", caller.file(), caller.line());
+        let mut record = CallRecord {
+            magic: MAGIC,
+            uptime_ms: start.duration_since_epoch().as_millis(),
+            call: call as u8 + 1,
+            app_len: 0,
+            location_len: 0,
+            app: [0; APP_LEN],
+            location: [0; LOCATION_LEN],
+        };
+        record.app_len = copy(&mut record.app, app);
+        record.location_len = copy(&mut record.location, &location);
+        // SAFETY: Only used by the UI task.
+        unsafe { core::ptr::addr_of_mut!(RUNNING_CALL).write(record) };
+    }
+    /// Finish the call started with `begin`, returns the overrun if it took too long.
+    pub fn end(&mut self) -> Option<Overrun> {
+        // SAFETY: Only used by the UI task.
+        unsafe { (*core::ptr::addr_of_mut!(RUNNING_CALL)).call = 0 };
+
+        let (app, call, start) = self.running.take()?;
+        let took = start.elapsed();
+        if took <= call.budget() {
+            return None;
+        }
+        error!(
+            "{} took {}ms in {}, the budget is {}ms",
+            app,
+            took.as_millis(),
+            call.name(),
+            call.budget().as_millis()
+        );
+        Some(Overrun { app, call, took })
+    }
+}
+
+/// Store a crash report when the hardware watchdog reset the device during an app call.
+///
+/// Has to be called during boot, before the first app call.
+pub fn check_reset() {
+    let by_watchdog = matches!(
+        esp_hal::system::reset_reason(),
+        Some(
+            SocResetReason::CoreMwdt0
+                | SocResetReason::CoreMwdt1
+                | SocResetReason::CpuMwdt0
+                | SocResetReason::CpuMwdt1
+                | SocResetReason::CoreRtcWdt
+                | SocResetReason::CpuRtcWdt
+                | SocResetReason::SysRtcWdt
+        )
+    );
+
+    // SAFETY: Only used by the UI task.
+    let record = unsafe { &mut *core::ptr::addr_of_mut!(RUNNING_CALL) };
+    let call = match record.call {
+        1 => Some(AppCall::Update),
+        2 => Some(AppCall::Render),
+        _ => None,
+    };
+    if by_watchdog
+        && record.magic == MAGIC
+        && let Some(call) = call
+    {
+        let app = &record.app[..(record.app_len as usize).min(APP_LEN)];
+        let app = core::str::from_utf8(app).unwrap_or_default();
+        let location = &record.location[..(record.location_len as usize).min(LOCATION_LEN)];
+        let location = core::str::from_utf8(location).unwrap_or_default();
+        error!(
+            "Reset by the watchdog during {} of {} at {}",
+            call.name(),
+            app,
+            location
+        );
+
+        let mut message = String::new();
+        let _ = message.push_str("Watchdog reset, app hung in ");
+        let _ = message.push_str(call.name());
+        crash::record_report(&CrashReport {
+            message,
+            location: String::try_from(location).unwrap_or_default(),
+            app: String::try_from(app).unwrap_or_default(),
+            uptime_ms: record.uptime_ms,
+        });
+    }
+    record.magic = 0;
+}
+
+fn copy(dst: &mut [u8], src: &str) -> u8 {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src.as_bytes()[..len]);
+    len as u8
+}